@@ -1,7 +1,128 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
+use quote::quote;
+use std::collections::HashMap;
 use std::str::FromStr;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Attribute, Ident, LitInt, LitStr, Token};
+
+/// `NAME = code => "default message"`, doc comments allowed
+struct ErrorCodeDef {
+    attrs: Vec<Attribute>,
+    name: Ident,
+    code: LitInt,
+    message: LitStr,
+}
+
+impl Parse for ErrorCodeDef {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let code = input.parse()?;
+        input.parse::<Token![=>]>()?;
+        let message = input.parse()?;
+
+        Ok(ErrorCodeDef { attrs, name, code, message })
+    }
+}
+
+/**
+ * The error code registry: expands a list of `NAME = code => "message"` into
+ * the `ErrorCodes` and `Messages` constants and an `ERROR_CODES` table of
+ * `ErrorCode { name, code, message }`. Reusing a code or a name fails the build.
+ *
+ * ```
+ * pub struct ErrorCode {
+ *     name: &'static str,
+ *     code: u16,
+ *     message: &'static str,
+ * }
+ *
+ * codegen::error_codes! {
+ *     UNKNOWN = 900 => "Unknown",
+ *     NOT_FOUND = 904 => "Not found",
+ * }
+ *
+ * assert_eq!(ErrorCodes::NOT_FOUND, 904);
+ * assert_eq!(ERROR_CODES[0].name, "UNKNOWN");
+ * ```
+ *
+ * ```compile_fail
+ * pub struct ErrorCode {
+ *     name: &'static str,
+ *     code: u16,
+ *     message: &'static str,
+ * }
+ *
+ * codegen::error_codes! {
+ *     UNKNOWN = 900 => "Unknown",
+ *     NOT_FOUND = 900 => "Not found",
+ * }
+ * ```
+ **/
+#[proc_macro]
+pub fn error_codes(input: TokenStream) -> TokenStream {
+    let defs = parse_macro_input!(input with Punctuated::<ErrorCodeDef, Token![,]>::parse_terminated);
+
+    let mut codes: HashMap<u16, Ident> = HashMap::new();
+    let mut names: HashMap<String, u16> = HashMap::new();
+    let mut errors: Option<syn::Error> = None;
+    let mut push_error = |error: syn::Error| match errors.as_mut() {
+        Some(errors) => errors.combine(error),
+        None => errors = Some(error),
+    };
+
+    for def in &defs {
+        let code = match def.code.base10_parse::<u16>() {
+            Ok(code) => code,
+            Err(err) => {
+                push_error(err);
+                continue;
+            },
+        };
+        if let Some(first) = codes.insert(code, def.name.clone()) {
+            let message = format!("error code {} is used by both {} and {}", code, first, def.name);
+            push_error(syn::Error::new(def.code.span(), message));
+        }
+        if names.insert(def.name.to_string(), code).is_some() {
+            push_error(syn::Error::new(def.name.span(), format!("{} is defined twice", def.name)));
+        }
+    }
+    if let Some(errors) = errors {
+        return errors.to_compile_error().into();
+    }
+
+    let attrs = defs.iter().map(|def| &def.attrs).collect::<Vec<_>>();
+    let names = defs.iter().map(|def| &def.name).collect::<Vec<_>>();
+    let codes = defs.iter().map(|def| &def.code).collect::<Vec<_>>();
+    let messages = defs.iter().map(|def| &def.message).collect::<Vec<_>>();
+
+    let expanded = quote! {
+        pub struct ErrorCodes {}
+
+        #[allow(unused)]
+        impl ErrorCodes {
+            #( #(#attrs)* pub const #names: u16 = #codes; )*
+        }
+
+        pub struct Messages {}
+
+        #[allow(unused)]
+        impl Messages {
+            #( pub const #names: &'static str = #messages; )*
+        }
+
+        /// Every error code, in declaration order
+        pub const ERROR_CODES: &[ErrorCode] = &[
+            #( ErrorCode { name: stringify!(#names), code: #codes, message: #messages }, )*
+        ];
+    };
+
+    expanded.into()
+}
 
 #[proc_macro]
 pub fn make_answer(_item: TokenStream) -> TokenStream {
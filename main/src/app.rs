@@ -1,69 +1,178 @@
+use crate::components::cache::{CacheBackend, MemoryCache};
 use crate::components::databases::redis_db::RedisDB;
-use crate::config::CONFIG;
-use crate::middlewares::before_action_middleware;
+use crate::components::startup::{self, StartupError};
+use crate::components::{logger, shutdown, tls};
+use crate::config::{get_config, reload, Config, Environment, CONFIG};
+use crate::errors::AppError;
+use crate::middlewares::{
+    admin_auth_middleware, before_action_middleware, catch_panic_middleware, localize_middleware, metrics_middleware,
+    report_middleware,
+};
 use crate::routes;
-use crate::services::iam_service::get_iam_keys_for_init;
+use crate::services::gapo_api_service::{HttpUserCoreClient, UserCoreClient};
+use crate::services::iam_service::{refresh_iam_keys, HttpIamClient, IamKeys};
+use actix_rt::time::timeout;
+use actix_service::ServiceFactory;
+use actix_web::body::Body;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::web::{Data, ServiceConfig};
-use actix_web::{web, App, HttpServer};
-use async_std::task;
-use proptest::std_facade::hash_map::RandomState;
-use proptest::std_facade::HashMap;
+use actix_web::{web, App, Error, HttpServer};
 use sentry::ClientInitGuard;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub struct Application {}
 
 impl Application {
     pub fn init() {
-        if cfg!(test) {
-            dotenv::from_filename(".env.test").ok();
-        } else {
-            dotenv::from_filename(".env").ok();
-        }
         // env::set_var("RUST_BACKTRACE", "1");
-        let config = &CONFIG;
+        let config = CONFIG.get();
 
-        std::env::set_var("RUST_LOG", config.rust_log.clone());
+        if !cfg!(test) {
+            logger::init(&config.rust_log, config.log_format);
+        }
     }
 
-    pub fn init_sentry(sentry_url: String) -> ClientInitGuard {
-        // init sentry
-        let dsn = sentry::types::Dsn::from_str(&sentry_url.as_str()).unwrap();
+    pub fn init_sentry(config: &Config) -> ClientInitGuard {
+        // The DSN was validated with the rest of the config
+        let dsn = sentry::types::Dsn::from_str(config.sentry_url.expose()).ok();
         let opt = sentry::ClientOptions {
             release: sentry::release_name!(),
-            dsn: Some(dsn),
-            debug: true,
+            dsn,
+            debug: config.sentry_debug,
+            environment: Some(config.env.as_str().into()),
             ..Default::default()
         };
         sentry::init(opt)
     }
 
     #[allow(unused)]
-    pub fn env_is(env: String) -> bool {
-        let config = &CONFIG;
+    pub fn env_is(env: Environment) -> bool {
+        let config = CONFIG.get();
 
         config.env == env
     }
+}
 
-    pub fn config_app() -> Box<dyn Fn(&mut ServiceConfig)> {
-        Box::new(move |cfg: &mut ServiceConfig| {
-            routes::init_routes(cfg);
-            Application::config_database(cfg);
-        })
+/// Builds the App from explicit dependencies, so the server, the tests and
+/// anything embedding the service share the same routes and middlewares
+///
+/// `new` starts with an in-memory cache and the HTTP clients for IAM and
+/// user-core; the setters swap in other implementations.
+#[derive(Clone)]
+pub struct AppBuilder {
+    config: Arc<Config>,
+    cache: Data<dyn CacheBackend>,
+    iam_keys: Data<IamKeys>,
+    user_core: Data<dyn UserCoreClient>,
+}
+
+impl AppBuilder {
+    pub fn new(config: Arc<Config>) -> Self {
+        AppBuilder {
+            config,
+            cache: Data::from(Arc::new(MemoryCache::new()) as Arc<dyn CacheBackend>),
+            iam_keys: Data::new(IamKeys::new(HttpIamClient)),
+            user_core: Data::from(Arc::new(HttpUserCoreClient) as Arc<dyn UserCoreClient>),
+        }
+    }
+
+    pub fn cache<C: CacheBackend + 'static>(mut self, cache: C) -> Self {
+        self.cache = Data::from(Arc::new(cache) as Arc<dyn CacheBackend>);
+        self
+    }
+
+    /// Shared with whoever refreshes the keys
+    pub fn iam_keys(mut self, iam_keys: Data<IamKeys>) -> Self {
+        self.iam_keys = iam_keys;
+        self
+    }
+
+    #[allow(unused)]
+    pub fn user_core<U: UserCoreClient + 'static>(mut self, user_core: U) -> Self {
+        self.user_core = Data::from(Arc::new(user_core) as Arc<dyn UserCoreClient>);
+        self
     }
 
-    pub fn config_database(cfg: &mut actix_web::web::ServiceConfig) {
-        let config = &CONFIG;
-        let redis = RedisDB::connect(config.redis_uri.clone());
+    /// Routes, request limits and the dependencies as app data
+    pub fn configure(&self, cfg: &mut ServiceConfig) {
+        routes::init_routes(cfg);
+        if !self.config.admin.enabled() {
+            routes::init_ops_routes(cfg);
+        }
+        self.configure_data(cfg);
+    }
 
-        cfg.data(redis.clone());
+    /// Routes of the admin listener
+    pub fn configure_admin(&self, cfg: &mut ServiceConfig) {
+        routes::init_ops_routes(cfg);
+        routes::init_admin_routes(cfg);
+        self.configure_data(cfg);
     }
 
-    pub async fn get_iam_keys() -> Data<Mutex<HashMap<String, String>>> {
-        let hash_map = get_iam_keys_for_init().await;
+    fn configure_data(&self, cfg: &mut ServiceConfig) {
+        let max_request_size = self.config.server.max_request_size;
 
-        web::Data::new(Mutex::new(hash_map))
+        cfg.app_data(web::PayloadConfig::new(max_request_size));
+        // Extractor failures are answered like any other ApiError, with the fields at fault
+        cfg.app_data(
+            web::JsonConfig::default()
+                .limit(max_request_size)
+                .error_handler(|err, _| AppError::from(err).into()),
+        );
+        cfg.app_data(web::QueryConfig::default().error_handler(|err, _| AppError::from(err).into()));
+        cfg.app_data(web::PathConfig::default().error_handler(|err, _| AppError::from(err).into()));
+        cfg.app_data(self.cache.clone());
+        cfg.app_data(self.iam_keys.clone());
+        cfg.app_data(self.user_core.clone());
+    }
+
+    pub fn build(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            Config = (),
+            Request = ServiceRequest,
+            Response = ServiceResponse<Body>,
+            Error = Error,
+            InitError = (),
+        >,
+        Body,
+    > {
+        let builder = self.clone();
+
+        App::new()
+            .configure(move |cfg| builder.configure(cfg))
+            .wrap(before_action_middleware::BeforeAction)
+            .wrap(catch_panic_middleware::CatchPanic)
+            .wrap(report_middleware::Report)
+            .wrap(localize_middleware::Localize)
+            .wrap(metrics_middleware::Metrics)
+    }
+
+    /// App of the admin listener, behind `AdminAuth` instead of `BeforeAction`
+    pub fn build_admin(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            Config = (),
+            Request = ServiceRequest,
+            Response = ServiceResponse<Body>,
+            Error = Error,
+            InitError = (),
+        >,
+        Body,
+    > {
+        let builder = self.clone();
+
+        App::new()
+            .configure(move |cfg| builder.configure_admin(cfg))
+            .wrap(admin_auth_middleware::AdminAuth)
+            .wrap(catch_panic_middleware::CatchPanic)
+            .wrap(report_middleware::Report)
+            .wrap(localize_middleware::Localize)
     }
 }
 
@@ -71,35 +180,188 @@ pub struct Server {}
 
 impl Server {
     pub async fn run() -> std::io::Result<()> {
-        let config = &CONFIG;
+        // Validate everything up front so a bad deployment reports every problem at once
+        if let Err(err) = get_config() {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+
+        let config = CONFIG.get();
 
         Application::init();
-        let dsn = sentry::types::Dsn::from_str(&config.sentry_url.as_str()).unwrap();
-        let opt = sentry::ClientOptions {
-            release: sentry::release_name!(),
-            dsn: Some(dsn),
-            debug: true,
-            ..Default::default()
+        let sentry = Application::init_sentry(&config);
+        // Connections shared by every worker, so they can be closed once on shutdown
+        let redis = match RedisDB::connect(config.redis_uri.expose().clone(), &config.redis) {
+            Ok(redis) => redis,
+            Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())),
         };
-        let _sentry = sentry::init(opt);
-        //
-        // let _sentry = Application::init_sentry(config.sentry_url.clone());
-        let iam_keys = Application::get_iam_keys().await;
+        let iam_keys = Data::new(IamKeys::new(HttpIamClient));
+        if let Err(err) = Server::wait_for_dependencies(&config, &redis, &iam_keys).await {
+            error!("{}", err);
+            sentry::capture_error(&err);
+            sentry.close(None);
+            return Err(std::io::Error::other(err));
+        }
+        let builder = AppBuilder::new(config.clone()).cache(redis.clone()).iam_keys(iam_keys.clone());
+
+        // Runtime tunable settings are reloaded on SIGHUP or when a config file changes
+        shutdown::spawn(reload::watch_signals());
+        shutdown::spawn(reload::watch_files());
+        shutdown::spawn(refresh_iam_keys(iam_keys.clone()));
 
         // start server
-        HttpServer::new(move || {
-            App::new()
-                .configure(Application::config_app())
-                .app_data(iam_keys.clone())
-                .wrap(before_action_middleware::BeforeAction)
+        let server = &config.server;
+        let public = builder.clone();
+        let http_server = HttpServer::new(move || public.build())
+            .workers(server.workers)
+            .backlog(server.backlog)
+            .max_connections(server.max_connections)
+            .keep_alive(server.keep_alive)
+            .client_timeout(server.client_timeout)
+            .client_shutdown(server.client_shutdown)
+            .shutdown_timeout(server.shutdown_timeout)
+            .disable_signals();
+
+        // Every listener is bound before any starts, so an address in use leaves nothing running
+        let bound = (|| {
+            #[cfg(feature = "openssl")]
+            let http_server = if config.tls.enabled() {
+                info!("Serving HTTPS on {}", server.bind);
+                http_server
+                    .on_connect(tls::on_connect)
+                    .bind_openssl(server.bind.as_str(), tls::acceptor(&config.tls)?)?
+            } else {
+                http_server.bind(server.bind.as_str())?
+            };
+            #[cfg(not(feature = "openssl"))]
+            let http_server = http_server.bind(server.bind.as_str())?;
+            let redirect = match &config.tls.redirect_bind {
+                Some(redirect_bind) => Some(Server::bind_redirect(redirect_bind, &server.bind)?),
+                None => None,
+            };
+            let admin = match &config.admin.bind {
+                Some(admin_bind) => Some(Server::bind_admin(admin_bind, builder)?),
+                None => None,
+            };
+
+            Ok::<_, std::io::Error>((http_server, redirect, admin))
+        })();
+        let (http_server, redirect, admin) = match bound {
+            Ok(bound) => bound,
+            Err(err) => {
+                error!("{}", err);
+                sentry.close(None);
+                redis.close().await;
+                return Err(err);
+            },
+        };
+
+        let http_server = http_server.run();
+        if let Some(run_redirect) = redirect {
+            run_redirect();
+        }
+        if let Some(run_admin) = admin {
+            run_admin();
+        }
+
+        // Stop accepting on SIGTERM/SIGINT and give in-flight requests SERVER_SHUTDOWN_TIMEOUT to finish
+        let stopping = http_server.clone();
+        actix_rt::spawn(async move {
+            let name = shutdown::signalled().await;
+            info!("Received {}, draining requests for up to {}s", name, CONFIG.get().server.shutdown_timeout);
+            shutdown::trigger();
+            stopping.stop(true).await;
+        });
+
+        let result = http_server.await;
+        shutdown::trigger();
+
+        if sentry.is_enabled() && !sentry.close(None) {
+            warn!("Sentry did not flush every pending event before its shutdown timeout");
+        }
+        redis.close().await;
+        info!("Shutdown complete");
+
+        result
+    }
+
+    /**
+     * Retry Redis and IAM until they answer or STARTUP_DEADLINE passes,
+     * failing only for the dependencies whose policy is `required`; each
+     * attempt gets HEALTH_TIMEOUT, as a probe would
+     **/
+    pub(crate) async fn wait_for_dependencies(config: &Config, redis: &RedisDB, iam_keys: &IamKeys) -> Result<(), StartupError> {
+        let startup = &config.startup;
+        let limit = Duration::from_millis(config.health.timeout);
+
+        startup::wait_for("redis", startup.redis, startup, || async {
+            redis.ping(limit).await.map_err(|err| err.to_string())
+        })
+        .await?;
+
+        let loaded = startup::wait_for("iam", startup.iam, startup, || async {
+            match timeout(limit, iam_keys.load()).await {
+                Ok(Ok(0)) => Err("IAM returned no service keys".to_string()),
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(err)) => Err(err.to_string()),
+                Err(_) => Err(format!("IAM did not answer within {}ms", limit.as_millis())),
+            }
         })
-            .bind(config.server.as_str())
-            .unwrap()
-            .keep_alive(0)
-            .client_shutdown(1000)
-            .client_timeout(1000)
+        .await?;
+        if !loaded {
+            warn!("Service requests are rejected until the IAM keys load");
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Listener for health, metrics and the admin routes, bound now and started
+     * by the returned function, then stopped on shutdown
+     **/
+    fn bind_admin(admin_bind: &str, builder: AppBuilder) -> std::io::Result<impl FnOnce()> {
+        let admin = HttpServer::new(move || builder.build_admin())
             .workers(1)
-            .run()
-            .await
+            .disable_signals()
+            .bind(admin_bind)?;
+        let admin_bind = admin_bind.to_string();
+
+        Ok(move || {
+            let admin = admin.run();
+            info!("Serving the admin routes on {}", admin_bind);
+
+            actix_rt::spawn(async move {
+                shutdown::wait().await;
+                admin.stop(true).await;
+            });
+        })
+    }
+
+    /**
+     * Plain HTTP listener that redirects to the HTTPS one, bound now and
+     * started by the returned function, then stopped on shutdown
+     **/
+    fn bind_redirect(redirect_bind: &str, https_bind: &str) -> std::io::Result<impl FnOnce()> {
+        let port = https_bind.parse::<SocketAddr>().map(|addr| addr.port()).unwrap_or(443);
+
+        let redirect = HttpServer::new(move || {
+            App::new()
+                .data(tls::HttpsPort(port))
+                .default_service(web::to(tls::redirect_to_https))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(redirect_bind)?;
+        let redirect_bind = redirect_bind.to_string();
+
+        Ok(move || {
+            let redirect = redirect.run();
+            info!("Redirecting HTTP on {} to HTTPS", redirect_bind);
+
+            actix_rt::spawn(async move {
+                shutdown::wait().await;
+                redirect.stop(true).await;
+            });
+        })
     }
 }
@@ -0,0 +1,220 @@
+//! Command line interface of the `main` binary
+//!
+//! `serve` (the default) starts the HTTP server, the other subcommands are
+//! operational helpers that load the same configuration as the server.
+
+use std::path::PathBuf;
+use std::process;
+
+use structopt::StructOpt;
+
+use crate::app::{Application, Server};
+use crate::components::cache::{self, CacheBackend};
+use crate::components::databases::redis_db::RedisDB;
+use crate::config::{get_config, sources, Config, Overrides, CONFIG};
+use crate::routes::ROUTES;
+use crate::services::gapo_api_service::get_iam_keys;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "main", about = "Gapo service example built on actix-web")]
+pub struct Cli {
+    /// Config file (TOML or YAML), instead of CONFIG_FILE or config/<ENV>.toml
+    #[structopt(long, short = "c", global = true, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Override a setting, e.g. `--set SERVER_WORKERS=4`; may be repeated
+    #[structopt(long = "set", short = "s", global = true, number_of_values = 1, parse(try_from_str = parse_override))]
+    pub set: Vec<(String, String)>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+pub enum Command {
+    /// Start the HTTP server (default)
+    Serve,
+    /// Validate the configuration and print the effective, redacted values
+    CheckConfig,
+    /// IAM service keys
+    Iam(IamCommand),
+    /// Inspect and clear cached values in Redis
+    Cache(CacheCommand),
+    /// List the registered routes
+    Routes,
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+pub enum IamCommand {
+    /// Fetch the service keys from IAM and print them
+    Sync {
+        /// Print the api keys in full instead of masking them
+        #[structopt(long)]
+        show_keys: bool,
+    },
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+pub enum CacheCommand {
+    /// Print the value of a key
+    Get { key: String },
+    /// Delete one or more keys
+    Del {
+        #[structopt(required = true)]
+        keys: Vec<String>,
+    },
+    /// Delete every key starting with a prefix, e.g. `UserCore:`
+    FlushPrefix {
+        prefix: String,
+        /// Only list the keys that would be deleted
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+fn parse_override(pair: &str) -> Result<(String, String), String> {
+    sources::parse_pair(pair).map_err(|issue| issue.to_string())
+}
+
+impl Cli {
+    pub fn overrides(&self) -> Overrides {
+        Overrides {
+            config_file: self.config.clone(),
+            values: self.set.clone(),
+        }
+    }
+}
+
+/**
+ * Run a subcommand, exiting with status 1 when it fails
+ **/
+pub async fn run(command: Command) -> std::io::Result<()> {
+    if command == Command::Serve {
+        return Server::run().await;
+    }
+
+    let config = match get_config() {
+        Ok(config) => config,
+        Err(err) => exit_with(err),
+    };
+    if command == Command::CheckConfig {
+        check_config(&config);
+        return Ok(());
+    }
+
+    Application::init();
+    match command {
+        Command::Iam(IamCommand::Sync { show_keys }) => iam_sync(show_keys).await,
+        Command::Cache(cache) => run_cache(cache).await,
+        Command::Routes => print_routes(),
+        Command::Serve | Command::CheckConfig => {},
+    }
+
+    Ok(())
+}
+
+fn exit_with<E: std::fmt::Display>(err: E) -> ! {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+fn check_config(config: &Config) {
+    let values = serde_json::to_string_pretty(config).unwrap_or_default();
+
+    println!("Configuration is valid ({} environment)\n", config.env);
+    println!("{}\n", values);
+    println!("Sources:");
+    for (key, source) in &config.provenance {
+        println!("  {:<36} {}", key, source);
+    }
+    println!("  (every other setting uses its default)");
+}
+
+async fn iam_sync(show_keys: bool) {
+    let keys = match get_iam_keys().await {
+        Ok(keys) => keys,
+        Err(err) => exit_with(format!("Could not fetch IAM keys: {}", err)),
+    };
+
+    println!("{} service key(s) from {}", keys.len(), CONFIG.get().iam_api);
+    for key in keys {
+        let api_key = if show_keys { key.apiKey.clone() } else { mask(&key.apiKey) };
+        println!("  {:<32} {}", key.source, api_key);
+    }
+}
+
+fn mask(value: &str) -> String {
+    let visible: String = value.chars().take(4).collect();
+
+    format!("{}***", visible)
+}
+
+async fn run_cache(command: CacheCommand) {
+    let config = CONFIG.get();
+    let redis = match RedisDB::connect(config.redis_uri.expose().clone(), &config.redis) {
+        Ok(redis) => redis,
+        Err(err) => exit_with(err.to_string()),
+    };
+
+    match command {
+        CacheCommand::Get { key } => match redis.get::<Option<String>>(key).await {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => println!("(nil)"),
+            Err(err) => exit_with(err.to_string()),
+        },
+        CacheCommand::Del { keys } => match redis.del_many(&keys).await {
+            Ok(deleted) => println!("Deleted {} key(s)", deleted),
+            Err(err) => exit_with(err.to_string()),
+        },
+        CacheCommand::FlushPrefix { prefix, dry_run } => {
+            if dry_run {
+                let keys = match redis.keys_with_prefix(&prefix).await {
+                    Ok(keys) => keys,
+                    Err(err) => exit_with(err.to_string()),
+                };
+                keys.iter().for_each(|key| println!("{}", key));
+                println!("{} key(s) would be deleted", keys.len());
+                return;
+            }
+
+            match cache::flush_prefix(&redis, &prefix).await {
+                Ok(deleted) => println!("Deleted {} key(s) starting with {:?}", deleted, prefix),
+                Err(err) => exit_with(err.to_string()),
+            }
+        },
+    }
+}
+
+fn print_routes() {
+    for route in ROUTES {
+        println!("{:<7} {:<24} {:<7} {}", route.method, route.path, route.scope.as_str(), route.handler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli { Cli::from_iter_safe(args).unwrap() }
+
+    #[test]
+    fn parses_subcommands_and_global_overrides() {
+        let cli = parse(&["main", "cache", "flush-prefix", "UserCore:", "--dry-run", "--set", "env=test"]);
+
+        assert_eq!(
+            cli.command,
+            Some(Command::Cache(CacheCommand::FlushPrefix {
+                prefix: "UserCore:".to_string(),
+                dry_run: true,
+            }))
+        );
+        assert_eq!(cli.overrides().values, vec![("ENV".to_string(), "test".to_string())]);
+
+        let cli = parse(&["main", "-c", "config/prod.yaml"]);
+        assert_eq!(cli.command, None);
+        assert_eq!(cli.overrides().config_file, Some(PathBuf::from("config/prod.yaml")));
+
+        assert!(Cli::from_iter_safe(&["main", "--set", "WORKERS"]).is_err());
+        assert!(Cli::from_iter_safe(&["main", "cache", "del"]).is_err());
+    }
+}
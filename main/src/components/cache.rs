@@ -0,0 +1,198 @@
+//! Cache backends behind `Data<dyn CacheBackend>`
+//!
+//! Handlers and services only see the trait, so the App can run on Redis in
+//! production and on `MemoryCache` in tests or when embedded.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::components::databases::redis_db::RedisDB;
+use crate::errors::AppError;
+
+/// Size of a connection pool, for the metrics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolState {
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+/// String key/value cache with expiry
+#[async_trait(?Send)]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError>;
+
+    /// One entry per key, in order
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>, AppError>;
+
+    /// Store `value` for `expire_time` seconds, 0 keeps it forever
+    async fn set(&self, key: &str, value: &str, expire_time: usize) -> bool;
+
+    /// Delete keys, returning how many of them existed
+    async fn del_many(&self, keys: &[String]) -> Result<usize, AppError>;
+
+    /// Every key starting with `prefix`, matched literally
+    async fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, AppError>;
+
+    async fn ping(&self, timeout: Duration) -> Result<(), AppError>;
+
+    /// None for backends without a connection pool
+    fn pool_state(&self) -> Option<PoolState> { None }
+}
+
+#[async_trait(?Send)]
+impl CacheBackend for RedisDB {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError> {
+        RedisDB::get::<Option<String>>(self, key.to_string()).await
+    }
+
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>, AppError> {
+        RedisDB::mget(self, keys.to_vec()).await
+    }
+
+    async fn set(&self, key: &str, value: &str, expire_time: usize) -> bool {
+        RedisDB::set(self, key.to_string(), value.to_string(), expire_time).await
+    }
+
+    async fn del_many(&self, keys: &[String]) -> Result<usize, AppError> { RedisDB::del_many(self, keys).await }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, AppError> {
+        RedisDB::scan_match(self, &format!("{}*", glob_escape(prefix))).await
+    }
+
+    async fn ping(&self, timeout: Duration) -> Result<(), AppError> { RedisDB::ping(self, timeout).await }
+
+    /// One multiplexed connection per server, never checked out so always idle
+    fn pool_state(&self) -> Option<PoolState> {
+        let connections = self.connections() as u32;
+
+        Some(PoolState {
+            connections,
+            idle_connections: connections,
+        })
+    }
+}
+
+/// In-process cache, expired entries are dropped when they are read
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, (String, Option<Instant>)>>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self { MemoryCache::default() }
+
+    fn lookup(entries: &mut HashMap<String, (String, Option<Instant>)>, key: &str) -> Option<String> {
+        match entries.get(key) {
+            Some((_, Some(expires_at))) if *expires_at <= Instant::now() => {
+                entries.remove(key);
+                None
+            },
+            Some((value, _)) => Some(value.clone()),
+            None => None,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, AppError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        Ok(MemoryCache::lookup(&mut entries, key))
+    }
+
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>, AppError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        Ok(keys.iter().map(|key| MemoryCache::lookup(&mut entries, key)).collect())
+    }
+
+    async fn set(&self, key: &str, value: &str, expire_time: usize) -> bool {
+        let expires_at = if expire_time > 0 {
+            Some(Instant::now() + Duration::from_secs(expire_time as u64))
+        } else {
+            None
+        };
+        self.entries.lock().unwrap().insert(key.to_string(), (value.to_string(), expires_at));
+
+        true
+    }
+
+    async fn del_many(&self, keys: &[String]) -> Result<usize, AppError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        Ok(keys.iter().filter(|key| entries.remove(key.as_str()).is_some()).count())
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, AppError> {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<String> = entries.keys().filter(|key| key.starts_with(prefix)).cloned().collect();
+
+        Ok(keys.into_iter().filter(|key| MemoryCache::lookup(&mut entries, key).is_some()).collect())
+    }
+
+    async fn ping(&self, _timeout: Duration) -> Result<(), AppError> { Ok(()) }
+}
+
+/// Escape glob characters so the prefix is matched literally by SCAN
+fn glob_escape(prefix: &str) -> String {
+    let mut escaped = String::with_capacity(prefix.len());
+    for c in prefix.chars() {
+        if let '*' | '?' | '[' | ']' | '\\' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/**
+ * Delete every key starting with `prefix`, a batch at a time, returning how many existed
+ **/
+pub async fn flush_prefix(cache: &dyn CacheBackend, prefix: &str) -> Result<usize, AppError> {
+    let mut deleted = 0;
+    for chunk in cache.keys_with_prefix(prefix).await?.chunks(500) {
+        deleted += cache.del_many(chunk).await?;
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn memory_cache_expires_entries() {
+        let cache = MemoryCache::new();
+        cache.set("forever", "1", 0).await;
+        cache.set("expired", "2", 1).await;
+        cache.entries.lock().unwrap().get_mut("expired").unwrap().1 = Some(Instant::now());
+
+        let keys = vec!["forever".to_string(), "expired".to_string(), "missing".to_string()];
+        assert_eq!(cache.mget(&keys).await.unwrap(), vec![Some("1".to_string()), None, None]);
+        assert_eq!(cache.del_many(&keys).await.unwrap(), 1);
+        assert_eq!(cache.get("forever").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn flushes_keys_by_prefix() {
+        let cache = MemoryCache::new();
+        cache.set("UserCore:1", "1", 0).await;
+        cache.set("UserCore:2", "2", 0).await;
+        cache.set("Other:1", "3", 0).await;
+
+        assert_eq!(flush_prefix(&cache, "UserCore:").await.unwrap(), 2);
+        assert_eq!(cache.keys_with_prefix("").await.unwrap(), vec!["Other:1".to_string()]);
+    }
+
+    #[test]
+    fn escapes_glob_characters() {
+        assert_eq!(glob_escape("UserCore:"), "UserCore:");
+        assert_eq!(glob_escape("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+}
@@ -0,0 +1,299 @@
+//! The r2d2 based Redis client, behind the `blocking-redis` feature
+//!
+//! Every call blocks the calling thread until Redis answers, so it is only
+//! meant for scripts and tools without a runtime. The service itself uses the
+//! async `redis_db::RedisDB`.
+
+use crate::components::databases::redis_db::observe;
+use crate::components::metrics;
+use crate::errors::AppError;
+use core::result::Result as CoreResult;
+use r2d2_redis::redis::{FromRedisValue, RedisError, Value};
+use r2d2_redis::{
+    r2d2,
+    r2d2::{Pool, PooledConnection},
+    redis,
+    redis::{parse_redis_url, Commands},
+    RedisConnectionManager,
+};
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::str::from_utf8;
+use std::time::Duration;
+
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct RedisDB {
+    pub conn: Pool<RedisConnectionManager>,
+}
+
+#[allow(unused)]
+impl RedisDB {
+    /**
+     * Create the pool for a redis url; connections are opened in the
+     * background, so use `ping` to find out whether the server is up
+     **/
+    pub fn connect(addr: String) -> Result<Self, AppError> {
+        let host = match parse_redis_url(addr.as_str()) {
+            Ok(host) => host,
+            Err(_) => return Err(AppError::Internal("Could not parse redis url".to_string())),
+        };
+        let manage = RedisConnectionManager::new(host)?;
+
+        Ok(Self {
+            conn: Pool::builder().build_unchecked(manage),
+        })
+    }
+
+    /**
+     * Check that a pooled connection answers PING within `timeout`
+     **/
+    pub fn ping(&self, timeout: Duration) -> Result<(), AppError> {
+        let mut conn = self.conn.get_timeout(timeout)?;
+        observe("PING", redis::cmd("PING").query::<String>(conn.deref_mut()))?;
+
+        Ok(())
+    }
+
+    /**
+     * Close the pool, connections are dropped once every clone is gone
+     **/
+    pub fn close(self) {
+        let state = self.conn.state();
+
+        info!(
+            "Closing redis pool ({} connections, {} idle)",
+            state.connections, state.idle_connections
+        );
+        drop(self);
+    }
+
+    /**
+     * Check out a pooled connection, recording how long `command` waited for it
+     **/
+    fn connection(&self, command: &'static str) -> Result<PooledConnection<RedisConnectionManager>, r2d2::Error> {
+        let timer = metrics::REDIS_POOL_WAIT.with_label_values(&[command]).start_timer();
+        let conn = self.conn.get();
+        timer.observe_duration();
+
+        if conn.is_err() {
+            metrics::REDIS_ERRORS.with_label_values(&[command, "pool"]).inc();
+        }
+
+        conn
+    }
+
+    /**
+     * Get the value of key
+     **/
+    pub fn get<T: FromRedisValue>(&self, key: String) -> Result<T, AppError> {
+        let mut conn = self.connection("GET")?;
+
+        Ok(observe("GET", conn.get::<String, T>(key))?)
+    }
+
+    /**
+     * Get multi value of keys, missing keys read as "nil"
+     **/
+    pub fn mget(&self, keys: Vec<String>) -> Result<Vec<String>, AppError> {
+        let mut conn = self.connection("MGET")?;
+        let text = |val: &Value| match val {
+            Value::Nil => "nil".to_string(),
+            Value::Data(val) => from_utf8(val).unwrap_or("").to_string(),
+            _ => String::new(),
+        };
+
+        match observe("MGET", conn.get::<Vec<String>, Value>(keys))? {
+            Value::Bulk(vals) => Ok(vals.iter().map(text).collect()),
+            val @ Value::Data(_) | val @ Value::Nil => Ok(vec![text(&val)]),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /**
+     * Set key to hold the string value in expire_time seconds
+     **/
+    pub fn set(&self, key: String, value: String, expire_time: usize) -> bool {
+        let mut conn = match self.connection("SET") {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        let set_value = observe("SET", conn.set(&key, value));
+        if expire_time > 0 {
+            conn.expire(&key, expire_time).unwrap_or(0);
+        }
+
+        self::RedisDB::redis_result_bool(set_value)
+    }
+
+    /**
+     * Delete a key
+     **/
+    pub fn del(&self, key: String) -> bool {
+        let mut conn = match self.connection("DEL") {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        self::RedisDB::redis_result_bool(observe("DEL", conn.del(key)))
+    }
+
+    /**
+     * Delete many keys, returning how many of them existed
+     **/
+    pub fn del_many(&self, keys: &[String]) -> Result<usize, AppError> {
+        let mut conn = self.connection("DEL")?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        Ok(observe("DEL", conn.del(keys))?)
+    }
+
+    /**
+     * Find the keys matching a glob pattern with SCAN, which unlike KEYS
+     * does not block the server
+     **/
+    pub fn scan_match(&self, pattern: &str) -> Result<Vec<String>, AppError> {
+        let mut conn = self.connection("SCAN")?;
+        let keys = observe("SCAN", conn.scan_match::<&str, String>(pattern))?;
+
+        Ok(keys.collect())
+    }
+
+    /**
+     * Get hash key
+     **/
+    pub fn hget<T: FromRedisValue>(&self, key: String, field: String) -> Result<T, AppError> {
+        let mut conn = self.connection("HGET")?;
+
+        Ok(observe("HGET", conn.hget::<String, String, T>(key, field))?)
+    }
+
+    /**
+     * Set hash key to hold the string value in expire_time seconds
+     **/
+    pub fn hset(&self, key: String, field: String, value: String) -> bool {
+        let mut conn = match self.connection("HSET") {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        self::RedisDB::redis_result_bool(observe("HSET", conn.hset(&key, field, value)))
+    }
+
+    /**
+     * Delete a key
+     **/
+    pub fn hdel(&self, key: String, field: String) -> bool {
+        let mut conn = match self.connection("HDEL") {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        self::RedisDB::redis_result_bool(observe("HDEL", conn.hdel(key, field)))
+    }
+
+    /**
+     * Add elements to Set
+     **/
+    pub fn sadd(&self, key: String, value: String) -> bool {
+        let mut conn = match self.connection("SADD") {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        self::RedisDB::redis_result_bool(observe("SADD", conn.sadd(&key, value)))
+    }
+
+    /*
+     * Get hash all
+     **/
+    pub fn hgetall(&self, key_name: &str) -> Result<HashMap<String, String>, AppError> {
+        let mut conn = self.connection("HGETALL")?;
+
+        Ok(observe("HGETALL", conn.hgetall(key_name))?)
+    }
+
+    /**
+     * Get number item in list
+     */
+    pub fn llen(&self, key: &str) -> Result<usize, AppError> {
+        let mut conn = self.connection("LLEN")?;
+
+        Ok(observe("LLEN", conn.llen(key))?)
+    }
+
+    /**
+     * Push a item to end of list
+     */
+    pub fn rpush(&self, key: &str, items: &str) -> Result<usize, AppError> {
+        let mut conn = self.connection("RPUSH")?;
+
+        Ok(observe("RPUSH", conn.rpush(key, items))?)
+    }
+
+    /**
+     * Get number item in list and set expired for list
+     */
+    pub fn rpush_and_set_expire(&self, key: &str, item: &str, expire_rime: usize) -> Result<usize, AppError> {
+        let mut conn = self.connection("RPUSH")?;
+
+        let (len, _): (usize, usize) = observe(
+            "RPUSH",
+            redis::pipe()
+                .cmd("RPUSH")
+                .arg(key)
+                .arg(item)
+                .cmd("EXPIRE")
+                .arg(key)
+                .arg(expire_rime)
+                .query(conn.deref_mut()),
+        )?;
+
+        Ok(len)
+    }
+
+    /**
+     * Redis result
+     **/
+    pub fn redis_result(redis_result: CoreResult<Value, RedisError>) -> Result<Value, AppError> {
+        Ok(redis_result?)
+    }
+
+    /**
+     * Redis result boolean
+     **/
+    pub fn redis_result_bool(redis_result: CoreResult<Value, RedisError>) -> bool {
+        matches!(redis_result, Ok(value) if value != Value::Nil)
+    }
+
+    /**
+     * Set new value if key not exist
+     **/
+    pub fn set_nx(&self, key: String, value: String, expire_time: usize) -> bool {
+        let mut conn = match self.connection("SETNX") {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        let set_value = observe("SETNX", conn.set_nx::<&String, String, bool>(&key, value));
+        if set_value.unwrap_or(false) && expire_time > 0 {
+            conn.expire(&key, expire_time).unwrap_or(0);
+
+            return true;
+        }
+
+        false
+    }
+
+    /**
+     * Set a key's time to live in seconds.
+     **/
+    pub fn expire(&self, key: String, expire_time: usize) -> Result<usize, AppError> {
+        let mut conn = self.connection("EXPIRE")?;
+
+        Ok(observe("EXPIRE", conn.expire(key, expire_time))?)
+    }
+}
@@ -1 +1,4 @@
+#[cfg(feature = "blocking-redis")]
+pub(crate) mod blocking_redis_db;
 pub(crate) mod redis_db;
+pub(crate) mod redis_topology;
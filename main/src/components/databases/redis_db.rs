@@ -1,307 +1,737 @@
-use crate::errors::ApiError;
+//! Async Redis client on multiplexed connections
+//!
+//! Commands from every worker are pipelined over one connection per server,
+//! so a slow command only delays the requests waiting for its reply. A
+//! connection is opened on first use, and again on the next command after it
+//! breaks. `Topology` decides which server a command goes to: the one of
+//! REDIS_URI, the master the sentinels name, or the cluster node serving the
+//! key's slot. The r2d2 based client is in `blocking_redis_db`, behind the
+//! `blocking-redis` feature.
+//!
+//! Commands are sent one at a time rather than pipelined together: an error
+//! reply in the middle of a pipeline would shift the replies of everything
+//! else sharing the connection.
+
+use crate::components::databases::redis_topology::{self, group_by_slot, key_slot, Redirect, SlotMap, Topology};
+use crate::components::{metrics, reporting};
+use crate::config::RedisConfig;
+use crate::errors::AppError;
+use actix_rt::time::timeout;
 use core::result::Result as CoreResult;
-use r2d2_redis::redis::{FromRedisValue, RedisError, Value};
-use r2d2_redis::{
-    r2d2::Pool,
-    redis,
-    redis::{parse_redis_url, Commands},
-    RedisConnectionManager,
+use futures::future::try_join_all;
+use futures::lock::Mutex;
+use redis::aio::MultiplexedConnection;
+use redis::{
+    parse_redis_url, Client, Cmd, ConnectionAddr, ConnectionInfo, ErrorKind, FromRedisValue, IntoConnectionInfo,
+    RedisError, RedisResult, Value,
 };
 use std::collections::HashMap;
-use std::ops::DerefMut;
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long opening a connection, or asking a sentinel or node, may take before the command fails
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keys asked for per SCAN round trip
+const SCAN_COUNT: usize = 500;
+
+/// MOVED and ASK errors followed per command
+const MAX_REDIRECTS: usize = 5;
+
+/**
+ * Count failed commands and leave a breadcrumb for the request; a nil reply
+ * read as a value is a cache miss, not an error
+ **/
+pub(crate) fn observe<T>(command: &'static str, result: RedisResult<T>) -> RedisResult<T> {
+    let failed = match &result {
+        Err(err) if err.kind() != ErrorKind::TypeError => {
+            metrics::REDIS_ERRORS.with_label_values(&[command, err.category()]).inc();
+            true
+        },
+        _ => false,
+    };
+    reporting::breadcrumb("redis", command.to_string(), failed, Default::default());
+
+    result
+}
 
+/// Which server a command goes to
 #[derive(Clone, Debug)]
+enum Route<'a> {
+    /// The master; in a cluster, the one serving slot 0
+    Any,
+    /// The master serving the slot of the key
+    Key(&'a str),
+    /// A node by `host:port`, named by a redirect or for commands every master runs
+    Node(String),
+}
+
+/// Open connections and what is known of the topology, forgotten as servers break or move
+#[derive(Default)]
+struct State {
+    /// By `host:port`, or the REDIS_URI address in Standalone mode
+    nodes: HashMap<String, MultiplexedConnection>,
+    /// The master the sentinels named, until it breaks or steps down
+    master: Option<String>,
+    /// Empty until the first command, and again once a node broke or a slot moved
+    slots: SlotMap,
+}
+
+impl State {
+    /**
+     * Drop the connection to `node` and whatever pointed at it
+     **/
+    fn forget(&mut self, node: &str) {
+        self.nodes.remove(node);
+        self.master = None;
+        self.slots = SlotMap::default();
+    }
+}
+
+#[derive(Clone)]
 pub struct RedisDB {
-    pub conn: Pool<RedisConnectionManager>,
+    /// Address, password and database of REDIS_URI
+    info: ConnectionInfo,
+    /// Name of the REDIS_URI server in `State::nodes`
+    name: String,
+    topology: Arc<Topology>,
+    state: Arc<Mutex<State>>,
+    /// Open connections, readable without waiting for `state`
+    open: Arc<AtomicUsize>,
 }
 
 #[allow(unused)]
 impl RedisDB {
     /**
-     * Connect redis server
+     * Create the client for a redis url in the mode of `config`; connections
+     * are opened by the first command, so use `ping` to find out whether the
+     * servers are up
      **/
-    pub fn connect(addr: String) -> Self {
-        let host = parse_redis_url(addr.as_str()).expect("Could not parse redis url");
-        let manage = RedisConnectionManager::new(host).expect("Could not connect redis with host");
-        let pool_manager = Pool::builder().build(manage).expect("Could not pool connection redis");
-
-        info!("Redis Connected");
+    pub fn connect(addr: String, config: &RedisConfig) -> Result<Self, AppError> {
+        let info = match parse_redis_url(addr.as_str()) {
+            Ok(url) => url.into_connection_info()?,
+            Err(_) => return Err(AppError::Internal("Could not parse redis url".to_string())),
+        };
+        let name = match &*info.addr {
+            ConnectionAddr::Tcp(host, port) => format!("{}:{}", host, port),
+            ConnectionAddr::Unix(path) => path.display().to_string(),
+        };
 
-        Self { conn: pool_manager }
+        Ok(Self {
+            topology: Arc::new(Topology::new(&info, config)),
+            info,
+            name,
+            state: Arc::new(Mutex::new(State::default())),
+            open: Arc::new(AtomicUsize::new(0)),
+        })
     }
 
     /**
-     * Get the value of key
+     * Check that the servers answer PING within `limit`, opening the
+     * connections if needed; every master in a cluster
      **/
-    pub fn get<T: FromRedisValue>(&self, key: String) -> Result<T, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
+    pub async fn ping(&self, limit: Duration) -> Result<(), AppError> {
+        let ping = async {
+            for route in self.masters().await? {
+                self.query::<String>("PING", route, &redis::cmd("PING")).await?;
+            }
+
+            Ok(())
         };
 
-        match conn.get::<String, T>(key) {
-            Ok(val) => Ok(val),
-            Err(e) => return Err(ApiError::new(400, e.to_string(), 900, None, None)),
+        match timeout(limit, ping).await {
+            Ok(result) => result,
+            Err(_) => Err(AppError::CacheUnavailable(format!(
+                "PING timed out after {}ms",
+                limit.as_millis()
+            ))),
         }
     }
 
     /**
-     * Get multi value of keys
+     * Close the connections, commands already sent on them still get their reply;
+     * waits up to CONNECT_TIMEOUT for a command opening a connection
      **/
-    pub fn mget(&self, keys: Vec<String>) -> Result<Vec<String>, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
-        };
+    pub async fn close(self) {
+        info!("Closing {} redis connection(s)", self.open.load(Ordering::Relaxed));
+        match timeout(CONNECT_TIMEOUT, self.state.lock()).await {
+            Ok(mut state) => {
+                state.nodes.clear();
+                self.track(&state);
+            },
+            Err(_) => warn!(
+                "Skipped closing the redis connections, a command still held them after {}ms",
+                CONNECT_TIMEOUT.as_millis()
+            ),
+        }
+    }
+
+    /// Whether a connection is open, as far as the last command knows
+    pub fn connected(&self) -> bool { self.connections() > 0 }
+
+    /// Open connections, one per server used
+    pub fn connections(&self) -> usize { self.open.load(Ordering::Relaxed) }
 
-        match conn.get::<Vec<String>, Value>(keys) {
-            Ok(all_value) => {
-                let mut list = Vec::<String>::new();
-                match all_value {
-                    Value::Bulk(vals) => {
-                        for val in vals.iter() {
-                            let v = match val {
-                                Value::Nil => "nil",
-                                Value::Data(ref val) => match from_utf8(val) {
-                                    Ok(x) => x,
-                                    Err(_) => "",
-                                },
-                                _ => "",
-                            };
-
-                            list.push(v.to_string());
-                        }
-                    }
-                    Value::Data(v) => {
-                        list.push(from_utf8(v.as_ref()).unwrap().to_string());
-                    }
-                    Value::Nil => list.push("nil".to_string()),
-                    _ => {
-                        return Ok(Vec::<String>::new());
-                    }
+    fn track(&self, state: &State) { self.open.store(state.nodes.len(), Ordering::Relaxed); }
+
+    fn cluster(&self) -> bool { matches!(*self.topology, Topology::Cluster { .. }) }
+
+    /**
+     * Connection info for a node; the database only applies outside a cluster
+     **/
+    fn node_info(&self, node: &str) -> RedisResult<ConnectionInfo> {
+        match &*self.topology {
+            Topology::Standalone => Ok(self.info.clone()),
+            Topology::Sentinel { .. } => redis_topology::node_info(node, self.info.db, self.info.passwd.clone()),
+            Topology::Cluster { .. } => redis_topology::node_info(node, 0, self.info.passwd.clone()),
+        }
+    }
+
+    /**
+     * Address of the server `route` leads to, asking the sentinels or the
+     * cluster when that is not known yet
+     **/
+    async fn node_for(&self, route: &Route<'_>) -> Result<String, AppError> {
+        match (&*self.topology, route) {
+            (_, Route::Node(node)) => Ok(node.clone()),
+            (Topology::Standalone, _) => Ok(self.name.clone()),
+            (Topology::Sentinel { master, sentinels }, _) => {
+                if let Some(node) = self.state.lock().await.master.clone() {
+                    return Ok(node);
+                }
+                // Asked without holding `state`, the sentinels may take CONNECT_TIMEOUT each
+                let node = self.ask_sentinels(master, sentinels).await?;
+                self.state.lock().await.master = Some(node.clone());
+
+                Ok(node)
+            },
+            (Topology::Cluster { seeds }, _) => {
+                let slot = match route {
+                    Route::Key(key) => key_slot(key.as_bytes()),
+                    _ => 0,
                 };
 
-                Ok(list)
-            }
-            Err(err) => Err(ApiError::new(400, err.to_string(), 900, None, None)),
+                match self.slots(seeds).await?.node(slot) {
+                    Some(node) => Ok(node.to_string()),
+                    None => Err(AppError::CacheUnavailable(format!("no cluster node serves slot {}", slot))),
+                }
+            },
         }
     }
 
     /**
-     * Set key to hold the string value in expire_time seconds
+     * The slot map, loaded from the cluster when it is not known; `state` is
+     * not held while loading, so commands with a connection go on meanwhile
      **/
-    pub fn set(&self, key: String, value: String, expire_time: usize) -> bool {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return false,
+    async fn slots(&self, seeds: &[String]) -> Result<SlotMap, AppError> {
+        let nodes = {
+            let state = self.state.lock().await;
+            if !state.slots.is_empty() {
+                return Ok(state.slots.clone());
+            }
+
+            // Nodes already connected know the current map better than the seeds
+            let mut nodes: Vec<String> = state.nodes.keys().cloned().collect();
+            nodes.extend(seeds.iter().filter(|seed| !state.nodes.contains_key(*seed)).cloned());
+            nodes
         };
+        let slots = self.load_slots(&nodes).await?;
+        self.state.lock().await.slots = slots.clone();
+
+        Ok(slots)
+    }
 
-        let set_value = conn.set(&key, value);
-        if expire_time > 0 {
-            conn.expire(&key, expire_time).unwrap_or(0);
+    /**
+     * The address of the master the first sentinel that knows `master` gives
+     **/
+    async fn ask_sentinels(&self, master: &str, sentinels: &[String]) -> Result<String, AppError> {
+        let mut failures = vec![];
+        for sentinel in sentinels {
+            let ask = async {
+                let mut conn = Client::open(redis_topology::node_info(sentinel, 0, None)?)?
+                    .get_async_connection()
+                    .await?;
+
+                redis::cmd("SENTINEL")
+                    .arg("get-master-addr-by-name")
+                    .arg(master)
+                    .query_async::<_, Option<(String, u16)>>(&mut conn)
+                    .await
+            };
+
+            match timeout(CONNECT_TIMEOUT, ask).await {
+                Ok(Ok(Some((host, port)))) => return Ok(format!("{}:{}", host, port)),
+                Ok(Ok(None)) => failures.push(format!("{} does not monitor it", sentinel)),
+                Ok(Err(err)) => failures.push(format!("{}: {}", sentinel, err)),
+                Err(_) => failures.push(format!("{} timed out", sentinel)),
+            }
         }
 
-        self::RedisDB::redis_result_bool(set_value)
+        Err(AppError::CacheUnavailable(format!(
+            "no sentinel named the master of {} ({})",
+            master,
+            failures.join(", ")
+        )))
     }
 
     /**
-     * Delete a key
+     * The slot map of the first of `nodes` that answers CLUSTER SLOTS
      **/
-    pub fn del(&self, key: String) -> bool {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return false,
-        };
+    async fn load_slots(&self, nodes: &[String]) -> Result<SlotMap, AppError> {
+        let mut failures = vec![];
+        for node in nodes {
+            let load = async {
+                let mut conn = Client::open(self.node_info(node)?)?.get_async_connection().await?;
+
+                redis::cmd("CLUSTER").arg("SLOTS").query_async::<_, Value>(&mut conn).await
+            };
+
+            match timeout(CONNECT_TIMEOUT, load).await {
+                Ok(Ok(reply)) => match SlotMap::parse(&reply, node) {
+                    Some(slots) if !slots.is_empty() => return Ok(slots),
+                    _ => failures.push(format!("{} serves no slots", node)),
+                },
+                Ok(Err(err)) => failures.push(format!("{}: {}", node, err)),
+                Err(_) => failures.push(format!("{} timed out", node)),
+            }
+        }
 
-        self::RedisDB::redis_result_bool(conn.del(key))
+        Err(AppError::CacheUnavailable(format!(
+            "no cluster node returned the slots ({})",
+            failures.join(", ")
+        )))
     }
 
     /**
-     * Get hash key
+     * Every master a command such as SCAN has to run on
      **/
-    pub fn hget<T: FromRedisValue>(&self, key: String, field: String) -> Result<T, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
+    async fn masters(&self) -> Result<Vec<Route<'static>>, AppError> {
+        let seeds = match &*self.topology {
+            Topology::Cluster { seeds } => seeds,
+            _ => return Ok(vec![Route::Any]),
         };
 
-        match conn.hget::<String, String, T>(key, field) {
-            Ok(value) => Ok(value),
-            Err(err) => Err(ApiError::new(400, err.to_string(), 900, None, None)),
+        Ok(self.slots(seeds).await?.masters().into_iter().map(Route::Node).collect())
+    }
+
+    /**
+     * The connection `route` leads to, recording how long `command` waited for it to open
+     **/
+    async fn connection(
+        &self,
+        command: &'static str,
+        route: &Route<'_>,
+    ) -> Result<(String, MultiplexedConnection), AppError> {
+        let timer = metrics::REDIS_POOL_WAIT.with_label_values(&[command]).start_timer();
+        let conn = self.open(route).await;
+        timer.observe_duration();
+
+        if conn.is_err() {
+            metrics::REDIS_ERRORS.with_label_values(&[command, "pool"]).inc();
         }
+
+        conn
     }
 
     /**
-     * Set hash key to hold the string value in expire_time seconds
+     * The open connection to the server of `route`, connecting first if there is none;
+     * the driver is spawned on the current runtime
      **/
-    pub fn hset(&self, key: String, field: String, value: String) -> bool {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return false,
+    async fn open(&self, route: &Route<'_>) -> Result<(String, MultiplexedConnection), AppError> {
+        let node = self.node_for(route).await?;
+        if let Some(conn) = self.state.lock().await.nodes.get(&node) {
+            return Ok((node, conn.clone()));
+        }
+
+        // Not holding `state`, so a server that does not answer only holds up its own commands
+        let connect = async {
+            let (mut conn, driver) = Client::open(self.node_info(&node)?)?.get_multiplexed_async_connection().await?;
+            actix_rt::spawn(driver);
+            self.check_role(&node, &mut conn).await?;
+
+            Ok(conn)
+        };
+        let result = match timeout(CONNECT_TIMEOUT, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(AppError::CacheUnavailable(format!(
+                "could not connect to {} within {}s",
+                node,
+                CONNECT_TIMEOUT.as_secs()
+            ))),
         };
 
-        self::RedisDB::redis_result_bool(conn.hset(&key, field, value))
+        let mut state = self.state.lock().await;
+        let conn = match result {
+            // Another command may have connected meanwhile, its connection is kept
+            Ok(conn) => state.nodes.entry(node.clone()).or_insert(conn).clone(),
+            Err(err) => {
+                state.forget(&node);
+                self.track(&state);
+                return Err(err);
+            },
+        };
+        self.track(&state);
+
+        Ok((node, conn))
     }
 
     /**
-     * Delete a key
+     * Fail unless `node` is still the master, the sentinels may not have noticed a failover yet
      **/
-    pub fn hdel(&self, key: String, field: String) -> bool {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return false,
-        };
+    async fn check_role(&self, node: &str, conn: &mut MultiplexedConnection) -> Result<(), AppError> {
+        if let Topology::Sentinel { master, .. } = &*self.topology {
+            let role: Vec<Value> = redis::cmd("ROLE").query_async(conn).await?;
+            if !redis_topology::is_master(&role) {
+                return Err(AppError::CacheUnavailable(format!(
+                    "{} is no longer the master of {}",
+                    node, master
+                )));
+            }
+        }
 
-        self::RedisDB::redis_result_bool(conn.hdel(key, field))
+        Ok(())
     }
 
     /**
-     * Add elements to Set
+     * Run `cmd` on the server `route` leads to, following MOVED and ASK in a cluster
      **/
-    pub fn sadd(&self, key: String, value: String) -> bool {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return false,
-        };
+    async fn query<T: FromRedisValue>(
+        &self,
+        command: &'static str,
+        route: Route<'_>,
+        cmd: &Cmd,
+    ) -> Result<T, AppError> {
+        let mut redirected = None;
+        for _ in 0..=MAX_REDIRECTS {
+            let result = match &redirected {
+                None => self.send(command, &route, cmd).await?,
+                Some(Redirect::Moved(node)) => self.send(command, &Route::Node(node.clone()), cmd).await?,
+                Some(Redirect::Ask(node)) => self.ask(node, cmd).await?,
+            };
+
+            redirected = match &result {
+                Err(err) if self.cluster() => redis_topology::redirect(err),
+                _ => None,
+            };
+            match &redirected {
+                None => return Ok(observe(command, result)?),
+                // The slot map is stale, the next command reloads it
+                Some(Redirect::Moved(_)) => self.state.lock().await.slots = SlotMap::default(),
+                Some(Redirect::Ask(_)) => {},
+            }
+        }
 
-        self::RedisDB::redis_result_bool(conn.sadd(&key, value))
+        metrics::REDIS_ERRORS.with_label_values(&[command, "redirect"]).inc();
+        Err(AppError::Cache(format!("{} was redirected more than {} times", command, MAX_REDIRECTS)))
     }
 
-    /*
-     * Get hash all
+    /**
+     * Send `cmd` on the shared connection, dropping it when the command found
+     * it broken, or the server no longer the master
      **/
-    pub fn hgetall(&self, key_name: &String) -> Result<HashMap<String, String>, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
+    async fn send<T: FromRedisValue>(
+        &self,
+        command: &'static str,
+        route: &Route<'_>,
+        cmd: &Cmd,
+    ) -> Result<RedisResult<T>, AppError> {
+        let (node, mut conn) = self.connection(command, route).await?;
+        let result = cmd.query_async(&mut conn).await;
+
+        if let Err(err) = &result {
+            if err.is_io_error() || err.is_connection_dropped() || err.code() == Some("READONLY") {
+                let mut state = self.state.lock().await;
+                state.forget(&node);
+                self.track(&state);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /**
+     * Send `cmd` to the node importing its slot, on a connection of its own
+     * as ASKING only holds for the next command
+     **/
+    async fn ask<T: FromRedisValue>(&self, node: &str, cmd: &Cmd) -> Result<RedisResult<T>, AppError> {
+        let ask = async {
+            let mut conn = Client::open(self.node_info(node)?)?.get_async_connection().await?;
+            redis::cmd("ASKING").query_async::<_, ()>(&mut conn).await?;
+
+            cmd.query_async(&mut conn).await
         };
 
-        let map: HashMap<String, String> = conn.hgetall(key_name).unwrap();
+        match timeout(CONNECT_TIMEOUT, ask).await {
+            Ok(result) => Ok(result),
+            Err(_) => Err(AppError::CacheUnavailable(format!(
+                "{} did not answer within {}s",
+                node,
+                CONNECT_TIMEOUT.as_secs()
+            ))),
+        }
+    }
 
-        Ok(map)
+    /**
+     * Get the value of key
+     **/
+    pub async fn get<T: FromRedisValue>(&self, key: String) -> Result<T, AppError> {
+        self.query("GET", Route::Key(&key), &Cmd::get(&key)).await
     }
 
     /**
-     * Get number item in list
-     */
-    pub fn llen(&self, key: &String) -> Result<usize, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
+     * Get multi value of keys, None for missing keys and values that are not
+     * text; one MGET per slot in a cluster
+     **/
+    pub async fn mget(&self, keys: Vec<String>) -> Result<Vec<Option<String>>, AppError> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let text = |val: &Value| match val {
+            Value::Data(val) => from_utf8(val).ok().map(str::to_string),
+            _ => None,
         };
 
-        match conn.llen(key) {
-            Ok(value) => {
-                return Ok(value);
+        let groups = self.group(&keys);
+        let cmds: Vec<Cmd> = groups
+            .iter()
+            .map(|group| redis::cmd("MGET").arg(group.iter().map(|index| &keys[*index]).collect::<Vec<_>>()).clone())
+            .collect();
+        let replies = try_join_all(
+            groups
+                .iter()
+                .zip(&cmds)
+                .map(|(group, cmd)| self.query::<Vec<Value>>("MGET", Route::Key(&keys[group[0]]), cmd)),
+        )
+        .await?;
+
+        let mut values = vec![None; keys.len()];
+        for (group, reply) in groups.iter().zip(replies) {
+            for (index, value) in group.iter().zip(&reply) {
+                values[*index] = text(value);
             }
-            Err(err) => Err(ApiError::new(500, err.to_string(), 900, Some(err.to_string()), None)),
         }
+
+        Ok(values)
     }
 
     /**
-     * Push a item to end of list
-     */
-    pub fn rpush(&self, key: &String, items: &String) -> Result<usize, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
-        };
+     * Indexes of `keys` a multi-key command can take at once
+     **/
+    fn group(&self, keys: &[String]) -> Vec<Vec<usize>> {
+        if self.cluster() {
+            group_by_slot(keys)
+        } else {
+            vec![(0..keys.len()).collect()]
+        }
+    }
+
+    /**
+     * Set key to hold the string value in expire_time seconds
+     **/
+    pub async fn set(&self, key: String, value: String, expire_time: usize) -> bool {
+        let set_value = self.query::<Value>("SET", Route::Key(&key), &Cmd::set(&key, value)).await;
+        if set_value.is_ok() && expire_time > 0 {
+            self.query::<usize>("EXPIRE", Route::Key(&key), &Cmd::expire(&key, expire_time)).await.unwrap_or(0);
+        }
 
-        match conn.rpush(key, items) {
-            Ok(value) => {
-                return Ok(value);
+        self::RedisDB::redis_result_bool(set_value)
+    }
+
+    /**
+     * Delete a key
+     **/
+    pub async fn del(&self, key: String) -> bool {
+        self::RedisDB::redis_result_bool(self.query("DEL", Route::Key(&key), &Cmd::del(&key)).await)
+    }
+
+    /**
+     * Delete many keys, returning how many of them existed; one DEL per slot in a cluster
+     **/
+    pub async fn del_many(&self, keys: &[String]) -> Result<usize, AppError> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let groups = self.group(keys);
+        let cmds: Vec<Cmd> = groups
+            .iter()
+            .map(|group| Cmd::del(group.iter().map(|index| &keys[*index]).collect::<Vec<_>>()))
+            .collect();
+        let deleted = try_join_all(
+            groups
+                .iter()
+                .zip(&cmds)
+                .map(|(group, cmd)| self.query::<usize>("DEL", Route::Key(&keys[group[0]]), cmd)),
+        )
+        .await?;
+
+        Ok(deleted.into_iter().sum())
+    }
+
+    /**
+     * Find the keys matching a glob pattern with SCAN, which unlike KEYS
+     * does not block the server; on every master in a cluster
+     **/
+    pub async fn scan_match(&self, pattern: &str) -> Result<Vec<String>, AppError> {
+        let mut keys = vec![];
+
+        for route in self.masters().await? {
+            let mut cursor = 0u64;
+            loop {
+                let scan = redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(pattern)
+                    .arg("COUNT")
+                    .arg(SCAN_COUNT)
+                    .clone();
+                let (next, batch): (u64, Vec<String>) = self.query("SCAN", route.clone(), &scan).await?;
+                keys.extend(batch);
+
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
             }
-            Err(err) => Err(ApiError::new(500, err.to_string(), 900, Some(err.to_string()), None)),
         }
+
+        Ok(keys)
     }
 
     /**
-     * Get number item in list and set expired for list
+     * Get hash key
+     **/
+    pub async fn hget<T: FromRedisValue>(&self, key: String, field: String) -> Result<T, AppError> {
+        self.query("HGET", Route::Key(&key), &Cmd::hget(&key, field)).await
+    }
+
+    /**
+     * Set hash key to hold the string value in expire_time seconds
+     **/
+    pub async fn hset(&self, key: String, field: String, value: String) -> bool {
+        self::RedisDB::redis_result_bool(self.query("HSET", Route::Key(&key), &Cmd::hset(&key, field, value)).await)
+    }
+
+    /**
+     * Delete a key
+     **/
+    pub async fn hdel(&self, key: String, field: String) -> bool {
+        self::RedisDB::redis_result_bool(self.query("HDEL", Route::Key(&key), &Cmd::hdel(&key, field)).await)
+    }
+
+    /**
+     * Add elements to Set
+     **/
+    pub async fn sadd(&self, key: String, value: String) -> bool {
+        self::RedisDB::redis_result_bool(self.query("SADD", Route::Key(&key), &Cmd::sadd(&key, value)).await)
+    }
+
+    /*
+     * Get hash all
+     **/
+    pub async fn hgetall(&self, key_name: &str) -> Result<HashMap<String, String>, AppError> {
+        self.query("HGETALL", Route::Key(key_name), &Cmd::hgetall(key_name)).await
+    }
+
+    /**
+     * Get number item in list
      */
-    pub fn rpush_and_set_expire(&self, key: &String, item: &String, expire_rime: usize) -> Result<usize, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
-        };
+    pub async fn llen(&self, key: &str) -> Result<usize, AppError> {
+        self.query("LLEN", Route::Key(key), &Cmd::llen(key)).await
+    }
 
-        let (k1, k2): (usize, usize) = redis::pipe()
-            .cmd("RPUSH")
-            .arg(key)
-            .arg(item)
-            .cmd("EXPIRE")
-            .arg(key)
-            .arg(expire_rime)
-            .query(conn.deref_mut())
-            .unwrap();
+    /**
+     * Push a item to end of list
+     */
+    pub async fn rpush(&self, key: &str, items: &str) -> Result<usize, AppError> {
+        self.query("RPUSH", Route::Key(key), &Cmd::rpush(key, items)).await
+    }
 
-        Ok(k1)
+    /**
+     * Get number item in list and set expired for list
+     */
+    pub async fn rpush_and_set_expire(&self, key: &str, item: &str, expire_rime: usize) -> Result<usize, AppError> {
+        let len = self.query("RPUSH", Route::Key(key), &Cmd::rpush(key, item)).await?;
+        self.query::<usize>("EXPIRE", Route::Key(key), &Cmd::expire(key, expire_rime)).await?;
+
+        Ok(len)
     }
 
     /**
      * Redis result
      **/
-    pub fn redis_result(redis_result: CoreResult<Value, RedisError>) -> Result<Value, ApiError> {
-        // println!("redis_result: {:?}", redis_result);
-        match redis_result {
-            Ok(value) => Ok(value),
-            Err(err) => Err(ApiError::new(400, err.to_string(), 900, None, None)),
-        }
+    pub fn redis_result(redis_result: CoreResult<Value, RedisError>) -> Result<Value, AppError> {
+        Ok(redis_result?)
     }
 
     /**
      * Redis result boolean
      **/
-    pub fn redis_result_bool(redis_result: CoreResult<Value, RedisError>) -> bool {
-        match redis_result {
-            Ok(value) => match value {
-                Value::Nil => false,
-                _ => true,
-            },
-            Err(err) => false,
-        }
+    pub fn redis_result_bool<E>(redis_result: CoreResult<Value, E>) -> bool {
+        matches!(redis_result, Ok(value) if value != Value::Nil)
     }
 
     /**
      * Set new value if key not exist
      **/
-    pub fn set_nx(&self, key: String, value: String, expire_time: usize) -> bool {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return false,
-        };
-
-        let set_value = conn.set_nx::<&String, String, bool>(&key, value);
-
-        if set_value.is_ok() && set_value.unwrap() == true && expire_time > 0 {
-            conn.expire(&key, expire_time).unwrap_or(0);
+    pub async fn set_nx(&self, key: String, value: String, expire_time: usize) -> bool {
+        let set_value = self.query::<bool>("SETNX", Route::Key(&key), &Cmd::set_nx(&key, value)).await;
+        if set_value.unwrap_or(false) && expire_time > 0 {
+            self.query::<usize>("EXPIRE", Route::Key(&key), &Cmd::expire(&key, expire_time)).await.unwrap_or(0);
 
             return true;
         }
 
-        return false;
+        false
     }
 
     /**
      * Set a key's time to live in seconds.
      **/
-    pub fn expire(&self, key: String, expire_time: usize) -> Result<usize, ApiError> {
-        let mut conn = match self.conn.get() {
-            Ok(conn) => conn,
-            Err(err) => return Err(ApiError::new(400, err.to_string(), 900, None, None)),
-        };
-
-        match conn.expire(key, expire_time) {
-            Ok(value) => {
-                return Ok(value);
-            }
-            Err(err) => Err(ApiError::new(400, err.to_string(), 900, None, None)),
-        }
+    pub async fn expire(&self, key: String, expire_time: usize) -> Result<usize, AppError> {
+        self.query("EXPIRE", Route::Key(&key), &Cmd::expire(&key, expire_time)).await
     }
 }
 
 #[cfg(test)]
 mod tests {
-    #[test]
-    fn connect() {}
+    use super::*;
+    use crate::config::RedisMode;
+
+    fn config(mode: RedisMode) -> RedisConfig {
+        RedisConfig {
+            mode,
+            sentinel_master: Some("mymaster".to_string()),
+            // Nothing listens on port 1, connections are refused at once
+            sentinels: vec!["127.0.0.1:1".to_string()],
+            cluster_nodes: vec!["127.0.0.1:1".to_string()],
+        }
+    }
+
+    #[actix_rt::test]
+    async fn unreachable_servers_are_reported_unavailable() {
+        let redis = RedisDB::connect("redis://127.0.0.1:1/".to_string(), &config(RedisMode::Standalone)).unwrap();
+
+        let err = redis.ping(Duration::from_secs(2)).await.unwrap_err();
+        assert!(matches!(err, AppError::CacheUnavailable(_)), "{:?}", err);
+        assert!(!redis.connected());
+        assert_eq!(redis.get::<Option<String>>("key".to_string()).await.unwrap_err().code(), err.code());
+    }
+
+    #[actix_rt::test]
+    async fn unreachable_sentinels_and_cluster_nodes_are_reported_unavailable() {
+        let redis = RedisDB::connect("redis://127.0.0.1:1/2".to_string(), &config(RedisMode::Sentinel)).unwrap();
+        let err = redis.mget(vec!["a".to_string(), "b".to_string()]).await.unwrap_err();
+        assert!(matches!(&err, AppError::CacheUnavailable(cause) if cause.contains("mymaster")), "{:?}", err);
+
+        let redis = RedisDB::connect("redis://127.0.0.1:1/".to_string(), &config(RedisMode::Cluster)).unwrap();
+        assert_eq!(*redis.topology, Topology::Cluster {
+            seeds: vec!["127.0.0.1:1".to_string()],
+        });
+        let err = redis.scan_match("*").await.unwrap_err();
+        assert!(matches!(&err, AppError::CacheUnavailable(cause) if cause.contains("slots")), "{:?}", err);
+        assert!(!redis.connected());
+    }
 }
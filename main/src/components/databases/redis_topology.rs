@@ -0,0 +1,266 @@
+//! Where `RedisDB` sends each command
+//!
+//! Standalone sends everything to the server of REDIS_URI. Sentinel asks the
+//! sentinels which server is the master. Cluster hashes each key to one of
+//! 16384 slots and looks up the master serving that slot in the map CLUSTER
+//! SLOTS returns; MOVED and ASK errors name the node to retry on.
+
+use std::collections::BTreeMap;
+
+use redis::{ConnectionAddr, ConnectionInfo, ErrorKind, FromRedisValue, RedisError, RedisResult, Value};
+
+use crate::config::{RedisConfig, RedisMode};
+
+/// Hash slots of a cluster
+pub const SLOTS: u16 = 16384;
+
+/// How the servers are deployed, with the addresses needed to find them
+#[derive(Clone, Debug, PartialEq)]
+pub enum Topology {
+    Standalone,
+    Sentinel {
+        /// Name the sentinels monitor the master under
+        master: String,
+        sentinels: Vec<String>,
+    },
+    Cluster {
+        /// Nodes asked for the slot map, in order
+        seeds: Vec<String>,
+    },
+}
+
+impl Topology {
+    /**
+     * The topology of `config`; in Cluster mode the server of REDIS_URI is the first seed
+     **/
+    pub fn new(info: &ConnectionInfo, config: &RedisConfig) -> Self {
+        match config.mode {
+            RedisMode::Standalone => Topology::Standalone,
+            RedisMode::Sentinel => Topology::Sentinel {
+                master: config.sentinel_master.clone().unwrap_or_default(),
+                sentinels: config.sentinels.clone(),
+            },
+            RedisMode::Cluster => {
+                let mut seeds = vec![];
+                if let ConnectionAddr::Tcp(host, port) = &*info.addr {
+                    seeds.push(format!("{}:{}", host, port));
+                }
+                for node in &config.cluster_nodes {
+                    if !seeds.contains(node) {
+                        seeds.push(node.clone());
+                    }
+                }
+
+                Topology::Cluster { seeds }
+            },
+        }
+    }
+}
+
+/// Node to retry a command on, from a MOVED or ASK error
+#[derive(Clone, Debug, PartialEq)]
+pub enum Redirect {
+    /// The slot lives on that node now, the slot map is stale
+    Moved(String),
+    /// The slot is being migrated to that node, ask it this once
+    Ask(String),
+}
+
+/**
+ * Where a MOVED or ASK error, such as `MOVED 3999 127.0.0.1:6381`, sends the command
+ **/
+pub fn redirect(err: &RedisError) -> Option<Redirect> {
+    let addr = err.detail()?.split_ascii_whitespace().nth(1)?.to_string();
+
+    match err.kind() {
+        ErrorKind::Moved => Some(Redirect::Moved(addr)),
+        ErrorKind::Ask => Some(Redirect::Ask(addr)),
+        _ => None,
+    }
+}
+
+/**
+ * Connection info for a `host:port` node
+ **/
+pub fn node_info(addr: &str, db: i64, passwd: Option<String>) -> RedisResult<ConnectionInfo> {
+    let invalid = || RedisError::from((ErrorKind::InvalidClientConfig, "Invalid node address", addr.to_string()));
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+
+    Ok(ConnectionInfo {
+        addr: Box::new(ConnectionAddr::Tcp(host.to_string(), port)),
+        db,
+        passwd,
+    })
+}
+
+/**
+ * Whether a ROLE reply comes from a master
+ **/
+pub fn is_master(role: &[Value]) -> bool {
+    role.first().and_then(|role| String::from_redis_value(role).ok()).as_deref() == Some("master")
+}
+
+/// CRC16/XMODEM, the checksum cluster slots are computed with
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+/**
+ * Slot of a key; only the part between the first `{` and the next `}` is
+ * hashed when it is not empty, so `{user1}.a` and `{user1}.b` share a slot
+ **/
+pub fn key_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|byte| *byte == b'{')
+        .and_then(|open| {
+            let tag = &key[open + 1..];
+            tag.iter().position(|byte| *byte == b'}').filter(|len| *len > 0).map(|len| &tag[..len])
+        })
+        .unwrap_or(key);
+
+    crc16(hashed) % SLOTS
+}
+
+/**
+ * Indexes of `keys` grouped by slot, in slot order, as multi-key commands
+ * may only name keys of one slot in a cluster
+ **/
+pub fn group_by_slot(keys: &[String]) -> Vec<Vec<usize>> {
+    let mut groups: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+    for (index, key) in keys.iter().enumerate() {
+        groups.entry(key_slot(key.as_bytes())).or_default().push(index);
+    }
+
+    groups.into_values().collect()
+}
+
+/// Which master serves each slot
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SlotMap {
+    /// First slot, last slot and `host:port` of the master, by first slot
+    ranges: Vec<(u16, u16, String)>,
+}
+
+impl SlotMap {
+    /**
+     * Read a CLUSTER SLOTS reply from `node`, whose host stands in for the
+     * masters listed without an ip; None when the reply is malformed
+     **/
+    pub fn parse(reply: &Value, node: &str) -> Option<SlotMap> {
+        let node_host = node.rsplit_once(':').map_or(node, |(host, _)| host);
+        let items = match reply {
+            Value::Bulk(items) => items,
+            _ => return None,
+        };
+
+        let mut ranges = Vec::with_capacity(items.len());
+        for item in items {
+            let (start, end, master) = match item {
+                Value::Bulk(fields) if fields.len() >= 3 => (&fields[0], &fields[1], &fields[2]),
+                _ => return None,
+            };
+            let (host, port) = match master {
+                Value::Bulk(master) if master.len() >= 2 => {
+                    (String::from_redis_value(&master[0]).ok()?, u16::from_redis_value(&master[1]).ok()?)
+                },
+                _ => return None,
+            };
+            let host = if host.is_empty() || host == "?" { node_host.to_string() } else { host };
+            let (start, end) = (u16::from_redis_value(start).ok()?, u16::from_redis_value(end).ok()?);
+
+            ranges.push((start, end, format!("{}:{}", host, port)));
+        }
+        ranges.sort();
+
+        Some(SlotMap { ranges })
+    }
+
+    pub fn is_empty(&self) -> bool { self.ranges.is_empty() }
+
+    /// `host:port` of the master serving `slot`
+    pub fn node(&self, slot: u16) -> Option<&str> {
+        let index = self.ranges.partition_point(|(start, ..)| *start <= slot).checked_sub(1)?;
+        let (_, end, node) = &self.ranges[index];
+
+        if slot <= *end {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+    /// Every master serving a slot, once
+    pub fn masters(&self) -> Vec<String> {
+        let mut masters: Vec<String> = self.ranges.iter().map(|(.., node)| node.clone()).collect();
+        masters.sort();
+        masters.dedup();
+
+        masters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(text: &str) -> Value { Value::Data(text.as_bytes().to_vec()) }
+
+    #[test]
+    fn hashes_keys_to_slots() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"{user1000}.followers"));
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        // An empty tag hashes the whole key, and only the first tag counts
+        assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % SLOTS);
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+
+        let keys: Vec<String> = ["{a}1", "b", "{a}2", "c"].iter().map(|key| key.to_string()).collect();
+        let groups = group_by_slot(&keys);
+        assert_eq!(groups.len(), 3);
+        assert!(groups.contains(&vec![0, 2]));
+    }
+
+    #[test]
+    fn reads_the_slot_map_and_redirects() {
+        let range = |start: i64, end: i64, host: &str, port: i64| {
+            Value::Bulk(vec![
+                Value::Int(start),
+                Value::Int(end),
+                Value::Bulk(vec![data(host), Value::Int(port), data("id")]),
+            ])
+        };
+        let reply = Value::Bulk(vec![
+            range(5461, 10922, "10.0.0.2", 7001),
+            range(0, 5460, "", 7000),
+            range(10923, 16383, "10.0.0.3", 7002),
+        ]);
+
+        let slots = SlotMap::parse(&reply, "10.0.0.1:7000").unwrap();
+        assert_eq!(slots.node(0), Some("10.0.0.1:7000"));
+        assert_eq!(slots.node(5461), Some("10.0.0.2:7001"));
+        assert_eq!(slots.node(SLOTS - 1), Some("10.0.0.3:7002"));
+        assert_eq!(slots.masters().len(), 3);
+        assert_eq!(SlotMap::parse(&Value::Bulk(vec![range(0, 100, "a", 1)]), "b:1").unwrap().node(101), None);
+        assert_eq!(SlotMap::parse(&data("nope"), "b:1"), None);
+
+        let error = |kind, detail: &str| RedisError::from((kind, "signalled by the server", detail.to_string()));
+        let moved = error(ErrorKind::Moved, "3999 127.0.0.1:6381");
+        let ask = error(ErrorKind::Ask, "3999 127.0.0.1:6382");
+        assert_eq!(redirect(&moved), Some(Redirect::Moved("127.0.0.1:6381".to_string())));
+        assert_eq!(redirect(&ask), Some(Redirect::Ask("127.0.0.1:6382".to_string())));
+        assert!(node_info("no-port", 0, None).is_err());
+        assert!(is_master(&[data("master"), Value::Int(0), Value::Bulk(vec![])]));
+        assert!(!is_master(&[data("slave")]));
+    }
+}
@@ -0,0 +1,236 @@
+//! Error message catalogue, one file per locale keyed by `ErrorCodes` values
+//!
+//! vi comes from the error code registry, en is built in from `main/locales`. Files in I18N_DIR named
+//! `<locale>.toml` add locales or override single messages. The language of a
+//! response comes from the `x-gapo-lang` header, then `Accept-Language`, then
+//! I18N_DEFAULT_LOCALE.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use actix_web::http::HeaderMap;
+use serde::Deserialize;
+
+use crate::config::CONFIG;
+use crate::constants::error_codes::{ERROR_CODES, VIOLATIONS};
+
+/// Per request override of `Accept-Language`
+pub const LANGUAGE_HEADER: &str = "x-gapo-lang";
+
+/// The language of the messages in the error code registry
+pub const REGISTRY_LOCALE: &str = "vi";
+
+const BUILT_IN: &[(&str, &str)] = &[("en", include_str!("../../locales/en.toml"))];
+
+/// One locale file: `code = "message"` lines, then a `[violations]` table
+#[derive(Deserialize)]
+struct LocaleFile {
+    #[serde(default)]
+    violations: BTreeMap<String, String>,
+    #[serde(flatten)]
+    messages: BTreeMap<String, String>,
+}
+
+/// Messages by locale, then by error code; violation messages by locale, then by violation code
+#[derive(Debug, Default)]
+pub struct Catalogue {
+    locales: HashMap<String, HashMap<u16, String>>,
+    violations: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalogue {
+    /**
+     * Merge a locale TOML file into `locale`
+     **/
+    pub fn add(&mut self, locale: &str, content: &str) -> Result<(), String> {
+        let file: LocaleFile = toml::from_str(content).map_err(|err| err.to_string())?;
+        let locale = locale.to_lowercase();
+        let messages = self.locales.entry(locale.clone()).or_default();
+
+        for (code, message) in file.messages {
+            let code = code.parse::<u16>().map_err(|_| format!("`{}` is not an error code", code))?;
+            messages.insert(code, message);
+        }
+        self.violations.entry(locale).or_default().extend(file.violations);
+
+        Ok(())
+    }
+
+    /**
+     * The built-in locales plus the `*.toml` files of `dir`, if any
+     **/
+    pub fn load(dir: Option<&Path>) -> Self {
+        let mut catalogue = Catalogue::default();
+        catalogue.locales.insert(
+            REGISTRY_LOCALE.to_string(),
+            ERROR_CODES.iter().map(|entry| (entry.code, entry.message.to_string())).collect(),
+        );
+        catalogue.violations.insert(
+            REGISTRY_LOCALE.to_string(),
+            VIOLATIONS.iter().map(|(code, message)| (code.to_string(), message.to_string())).collect(),
+        );
+        for (locale, content) in BUILT_IN {
+            if let Err(err) = catalogue.add(locale, content) {
+                error!("Built-in {} messages are invalid: {}", locale, err);
+            }
+        }
+
+        let files = match dir.map(fs::read_dir) {
+            Some(Ok(files)) => files,
+            Some(Err(err)) => {
+                warn!("Could not read I18N_DIR: {}", err);
+                return catalogue;
+            },
+            None => return catalogue,
+        };
+        for path in files.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let locale = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(ext)) if ext == "toml" => stem.to_string_lossy().to_string(),
+                _ => continue,
+            };
+            let added = fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|content| catalogue.add(&locale, &content));
+            if let Err(err) = added {
+                warn!("Skipping messages in {}: {}", path.display(), err);
+            }
+        }
+
+        catalogue
+    }
+
+    pub fn has_locale(&self, locale: &str) -> bool { self.locales.contains_key(locale) }
+
+    /**
+     * The message for `code` in `locale` with its `{name}` parameters filled in
+     **/
+    pub fn message(&self, locale: &str, code: u16, params: &BTreeMap<String, String>) -> Option<String> {
+        let template = self.locales.get(locale)?.get(&code)?;
+
+        Some(fill(template, params))
+    }
+
+    /**
+     * The message for the violation `code` of `field` in `locale`
+     **/
+    pub fn violation(&self, locale: &str, code: &str, field: &str) -> Option<String> {
+        let template = self.violations.get(locale)?.get(code)?;
+        let mut params = BTreeMap::new();
+        params.insert("field".to_string(), field.to_string());
+
+        Some(fill(template, &params))
+    }
+
+    /**
+     * The first locale of the catalogue the request asks for
+     **/
+    pub fn negotiate(&self, headers: &HeaderMap, default: &str) -> String {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or("");
+
+        std::iter::once(header(LANGUAGE_HEADER).to_string())
+            .chain(accepted_languages(header("accept-language")))
+            .map(|tag| tag.split('-').next().unwrap_or("").trim().to_lowercase())
+            .find(|locale| self.has_locale(locale))
+            .unwrap_or_else(|| default.to_string())
+    }
+}
+
+/**
+ * Replace the `{name}` placeholders of `template`
+ **/
+pub fn fill(template: &str, params: &BTreeMap<String, String>) -> String {
+    params.iter().fold(template.to_string(), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// Language tags of an `Accept-Language` header, most preferred first
+fn accepted_languages(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim();
+            let quality = pieces
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if tag.is_empty() || tag == "*" || quality <= 0.0 {
+                None
+            } else {
+                Some((tag.to_string(), quality))
+            }
+        })
+        .collect();
+    // Stable, so equal qualities keep the order of the header
+    tags.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+lazy_static! {
+    pub static ref CATALOGUE: Catalogue = Catalogue::load(CONFIG.get().i18n.dir.as_deref());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn picks_the_override_then_accept_language_then_the_default() {
+        let catalogue = Catalogue::load(None);
+
+        assert_eq!(catalogue.negotiate(&headers(&[]), "vi"), "vi");
+        assert_eq!(catalogue.negotiate(&headers(&[("accept-language", "fr-FR, en-US;q=0.8, vi;q=0.9")]), "en"), "vi");
+        assert_eq!(catalogue.negotiate(&headers(&[("accept-language", "en-GB,vi;q=0")]), "vi"), "en");
+        assert_eq!(
+            catalogue.negotiate(&headers(&[("accept-language", "en"), (LANGUAGE_HEADER, "vi")]), "en"),
+            "vi"
+        );
+    }
+
+    #[test]
+    fn fills_in_parameters_and_accepts_overrides() {
+        let mut catalogue = Catalogue::load(None);
+        let mut params = BTreeMap::new();
+        params.insert("id".to_string(), "42".to_string());
+
+        assert_eq!(catalogue.message("en", 1003, &params).unwrap(), "User 42 does not exist or is blocked.");
+        assert_eq!(catalogue.message("fr", 1003, &params), None);
+
+        catalogue.add("en", "1003 = \"No user {id}\"").unwrap();
+        assert_eq!(catalogue.message("en", 1003, &params).unwrap(), "No user 42");
+        assert!(catalogue.add("en", "abc = \"x\"").is_err());
+    }
+
+    #[test]
+    fn built_in_locales_cover_the_registry() {
+        let catalogue = Catalogue::load(None);
+
+        for locale in &[REGISTRY_LOCALE, "en"] {
+            for entry in ERROR_CODES {
+                assert!(
+                    catalogue.message(locale, entry.code, &BTreeMap::new()).is_some(),
+                    "{} has no {} message",
+                    entry.name,
+                    locale
+                );
+            }
+            for (code, _) in VIOLATIONS {
+                assert!(catalogue.violation(locale, code, "name").is_some(), "{} has no {} message", code, locale);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::io::Write;
+use std::sync::RwLock;
+
+use env_logger::Logger;
+use log::{Log, Metadata, Record};
+use serde_json::json;
+
+use crate::config::LogFormat;
+
+/// env_logger whose filter can be replaced while the server is running
+pub struct ReloadableLogger {
+    inner: RwLock<Logger>,
+    format: RwLock<LogFormat>,
+    filter: RwLock<String>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.inner.read() {
+            Ok(logger) => logger.enabled(metadata),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(logger) = self.inner.read() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(logger) = self.inner.read() {
+            logger.flush();
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOGGER: ReloadableLogger = ReloadableLogger {
+        inner: RwLock::new(build("info", LogFormat::Pretty)),
+        format: RwLock::new(LogFormat::Pretty),
+        filter: RwLock::new("info".to_string()),
+    };
+}
+
+fn build(filter: &str, format: LogFormat) -> Logger {
+    let mut builder = env_logger::Builder::new();
+    builder.format_module_path(false).parse_filters(filter);
+
+    // One JSON object per line for log collectors
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = json!({
+                "timestamp": buf.timestamp_millis().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+
+    builder.build()
+}
+
+/**
+ * Install the logger with a RUST_LOG style filter
+ **/
+pub fn init(filter: &str, format: LogFormat) {
+    if let Ok(mut current) = LOGGER.format.write() {
+        *current = format;
+    }
+    set_filter(filter);
+
+    if log::set_logger(&*LOGGER).is_err() {
+        warn!("Logger is already installed");
+    }
+}
+
+/**
+ * Replace the RUST_LOG style filter of the running logger
+ **/
+pub fn set_filter(filter: &str) {
+    let format = LOGGER.format.read().map(|format| *format).unwrap_or(LogFormat::Pretty);
+    let logger = build(filter, format);
+    log::set_max_level(logger.filter());
+
+    if let Ok(mut current) = LOGGER.inner.write() {
+        *current = logger;
+    }
+    if let Ok(mut current) = LOGGER.filter.write() {
+        *current = filter.to_string();
+    }
+}
+
+/**
+ * The RUST_LOG style filter in use
+ **/
+pub fn filter() -> String { LOGGER.filter.read().map(|filter| filter.clone()).unwrap_or_default() }
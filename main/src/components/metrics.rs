@@ -0,0 +1,120 @@
+//! Prometheus metrics, rendered in the text format by `/metrics`
+//!
+//! Everything is registered in one registry so the endpoint only shows the
+//! metrics of this service. Label values must stay low cardinality: routes
+//! are recorded by pattern (`/users/{id}`), never by raw path.
+
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::components::cache::CacheBackend;
+
+/// Label used for requests that did not match any route
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+
+    pub static ref HTTP_REQUESTS: IntCounterVec = counter(
+        Opts::new("http_requests_total", "HTTP requests handled"),
+        &["method", "route", "status"],
+    );
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = histogram(
+        HistogramOpts::new("http_request_duration_seconds", "Time to produce the response")
+            .buckets(exponential_buckets(0.001, 2.5, 10).unwrap()),
+        &["method", "route", "status"],
+    );
+
+    pub static ref REDIS_POOL_CONNECTIONS: IntGauge = gauge("redis_pool_connections", "Connections in the Redis pool");
+    pub static ref REDIS_POOL_IDLE: IntGauge = gauge("redis_pool_idle_connections", "Idle connections in the Redis pool");
+    pub static ref REDIS_POOL_WAIT: HistogramVec = histogram(
+        HistogramOpts::new("redis_pool_wait_seconds", "Time spent waiting for a pooled Redis connection")
+            .buckets(exponential_buckets(0.0001, 4.0, 8).unwrap()),
+        &["command"],
+    );
+    pub static ref REDIS_ERRORS: IntCounterVec = counter(
+        Opts::new("redis_command_errors_total", "Failed Redis commands, `pool` when no connection was available"),
+        &["command", "kind"],
+    );
+
+    pub static ref UPSTREAM_DURATION: HistogramVec = histogram(
+        HistogramOpts::new("upstream_request_duration_seconds", "Latency of calls to other services")
+            .buckets(exponential_buckets(0.005, 2.0, 10).unwrap()),
+        &["upstream", "outcome"],
+    );
+    pub static ref UPSTREAM_ERRORS: IntCounterVec = counter(
+        Opts::new("upstream_request_errors_total", "Failed calls to other services"),
+        &["upstream", "kind"],
+    );
+
+    pub static ref CACHE_REQUESTS: IntCounterVec = counter(
+        Opts::new("cache_requests_total", "Cache lookups by result"),
+        &["cache", "result"],
+    );
+}
+
+fn counter(opts: Opts, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(opts, labels).expect("invalid counter");
+    REGISTRY.register(Box::new(counter.clone())).expect("counter registered twice");
+
+    counter
+}
+
+fn gauge(name: &str, help: &str) -> IntGauge {
+    let gauge = IntGauge::new(name, help).expect("invalid gauge");
+    REGISTRY.register(Box::new(gauge.clone())).expect("gauge registered twice");
+
+    gauge
+}
+
+fn histogram(opts: HistogramOpts, labels: &[&str]) -> HistogramVec {
+    let histogram = HistogramVec::new(opts, labels).expect("invalid histogram");
+    REGISTRY.register(Box::new(histogram.clone())).expect("histogram registered twice");
+
+    histogram
+}
+
+/**
+ * Count `hits` and `misses` of a cache lookup
+ **/
+pub fn cache_lookup(cache: &str, hits: usize, misses: usize) {
+    if hits > 0 {
+        CACHE_REQUESTS.with_label_values(&[cache, "hit"]).inc_by(hits as u64);
+    }
+    if misses > 0 {
+        CACHE_REQUESTS.with_label_values(&[cache, "miss"]).inc_by(misses as u64);
+    }
+}
+
+/**
+ * Every metric in the Prometheus text format, sampling the pool gauges first
+ **/
+pub fn render(cache: Option<&dyn CacheBackend>) -> String {
+    if let Some(state) = cache.and_then(|cache| cache.pool_state()) {
+        REDIS_POOL_CONNECTIONS.set(state.connections as i64);
+        REDIS_POOL_IDLE.set(state.idle_connections as i64);
+    }
+
+    let mut buffer = vec![];
+    if let Err(err) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        error!("Could not encode the metrics: {}", err);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_text_format() {
+        cache_lookup("metrics_test", 2, 1);
+        let text = render(None);
+
+        assert!(text.contains("# TYPE cache_requests_total counter"));
+        assert!(text.contains("cache_requests_total{cache=\"metrics_test\",result=\"hit\"} 2"));
+        assert!(text.contains("cache_requests_total{cache=\"metrics_test\",result=\"miss\"} 1"));
+    }
+}
@@ -1 +1,10 @@
+pub(crate) mod cache;
 pub(crate) mod databases;
+pub(crate) mod i18n;
+pub(crate) mod logger;
+pub(crate) mod metrics;
+pub(crate) mod reporting;
+pub(crate) mod shutdown;
+pub(crate) mod startup;
+pub(crate) mod tls;
+pub(crate) mod typed_cache;
@@ -0,0 +1,147 @@
+//! Error reporting to Sentry
+//!
+//! Every request runs on its own hub, bound by the `Report` middleware, so the
+//! tags, the user and the breadcrumbs of concurrent requests stay apart. Which
+//! errors are sent, at what level and how often comes from `SENTRY_*`.
+
+use std::sync::Arc;
+
+use sentry::protocol::{Breadcrumb, Event, Level, Map, User};
+use sentry::{Hub, Scope};
+use serde_json::{json, Value};
+
+use crate::config::{SentryConfig, CONFIG};
+use crate::errors::ApiError;
+
+/**
+ * The Sentry level of an error, None when it is not worth sending: 5xx are
+ * errors, the 4xx codes of `warning_codes` warnings, ignored codes never sent
+ **/
+pub fn level(config: &SentryConfig, http_code: u16, code: u16) -> Option<Level> {
+    if config.ignore_codes.contains(&code) {
+        return None;
+    }
+
+    match http_code {
+        500..=599 => Some(Level::Error),
+        400..=499 if config.warning_codes.contains(&code) => Some(Level::Warning),
+        _ => None,
+    }
+}
+
+/**
+ * Whether an error with `code` is sent, `roll` being uniform in [0, 1)
+ **/
+pub fn sampled(config: &SentryConfig, code: u16, roll: f32) -> bool {
+    roll < *config.sample_rates.get(&code).unwrap_or(&config.sample_rate)
+}
+
+/**
+ * Send `err` to the hub of the current request, if its level and sample rate say so
+ **/
+pub fn report(err: &ApiError) {
+    let config = &CONFIG.get().sentry;
+    let level = match level(config, err.http_code, err.code) {
+        Some(level) => level,
+        None => return,
+    };
+    if !sampled(config, err.code, rand::random()) {
+        return;
+    }
+
+    let mut tags = Map::new();
+    tags.insert("error_code".to_string(), err.code.to_string());
+    tags.insert("http_code".to_string(), err.http_code.to_string());
+    let mut extra = Map::new();
+    if let Some(cause) = &err.cause {
+        extra.insert("cause".to_string(), json!(cause));
+    }
+    if !err.violations.is_empty() {
+        extra.insert("violations".to_string(), json!(err.violations));
+    }
+    if let Some(info) = &err.info {
+        extra.insert("request".to_string(), json!(info));
+    }
+
+    sentry::capture_event(Event {
+        message: Some(err.message.clone()),
+        level,
+        stacktrace: err.backtrace.clone(),
+        tags,
+        extra,
+        ..Default::default()
+    });
+}
+
+/**
+ * A hub for one request, on the client of the main hub but with a scope of its own
+ **/
+pub fn request_hub(request_id: &str, method: &str, path: &str, user_id: Option<&str>, role: Option<&str>) -> Arc<Hub> {
+    let hub = Arc::new(Hub::new(Hub::main().client(), Arc::new(Scope::default())));
+
+    hub.configure_scope(|scope| {
+        scope.set_tag("request_id", request_id);
+        scope.set_tag("method", method);
+        scope.set_tag("uri", path);
+        if let Some(role) = role {
+            scope.set_tag("role", role);
+        }
+        scope.set_user(user_id.map(|id| User {
+            id: Some(id.to_string()),
+            ..Default::default()
+        }));
+    });
+
+    hub
+}
+
+/**
+ * Record a Redis command or an upstream call on the current request's hub
+ **/
+pub fn breadcrumb(category: &str, message: String, failed: bool, data: Map<String, Value>) {
+    sentry::add_breadcrumb(Breadcrumb {
+        category: Some(category.to_string()),
+        message: Some(message),
+        level: if failed { Level::Warning } else { Level::Info },
+        data,
+        ..Default::default()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn config() -> SentryConfig {
+        let mut sample_rates = BTreeMap::new();
+        sample_rates.insert(1101, 0.25);
+
+        SentryConfig {
+            sample_rate: 1.0,
+            sample_rates,
+            ignore_codes: vec![1105],
+            warning_codes: vec![1002],
+        }
+    }
+
+    #[test]
+    fn maps_status_and_code_to_a_level() {
+        let config = config();
+
+        assert_eq!(level(&config, 503, 1101), Some(Level::Error));
+        assert_eq!(level(&config, 403, 1002), Some(Level::Warning));
+        assert_eq!(level(&config, 401, 1002), Some(Level::Warning));
+        assert_eq!(level(&config, 400, 902), None);
+        assert_eq!(level(&config, 502, 1105), None);
+    }
+
+    #[test]
+    fn samples_per_code() {
+        let config = config();
+
+        assert!(sampled(&config, 1104, 0.99));
+        assert!(sampled(&config, 1101, 0.2));
+        assert!(!sampled(&config, 1101, 0.3));
+    }
+}
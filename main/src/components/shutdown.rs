@@ -0,0 +1,125 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use actix_rt::signal::unix::{signal, SignalKind};
+use futures::channel::oneshot;
+use futures::future::{self, FutureExt, Shared};
+
+/// Process wide shutdown flag that background tasks can wait on
+pub struct Shutdown {
+    triggered: AtomicBool,
+    sender: Mutex<Option<oneshot::Sender<()>>>,
+    receiver: Shared<oneshot::Receiver<()>>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        let (sender, receiver) = oneshot::channel();
+
+        Shutdown {
+            triggered: AtomicBool::new(false),
+            sender: Mutex::new(Some(sender)),
+            receiver: receiver.shared(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref SHUTDOWN: Shutdown = Shutdown::new();
+}
+
+/**
+ * Tell every background task to stop, only the first call has an effect
+ **/
+pub fn trigger() {
+    SHUTDOWN.triggered.store(true, Ordering::SeqCst);
+
+    if let Ok(mut sender) = SHUTDOWN.sender.lock() {
+        if let Some(sender) = sender.take() {
+            sender.send(()).ok();
+        }
+    }
+}
+
+#[allow(unused)]
+pub fn is_triggered() -> bool { SHUTDOWN.triggered.load(Ordering::SeqCst) }
+
+/**
+ * Resolves once shutdown has been triggered
+ **/
+pub async fn wait() {
+    SHUTDOWN.receiver.clone().await.ok();
+}
+
+/**
+ * Spawn a background task on the current arbiter that is dropped on shutdown
+ **/
+pub fn spawn<F: Future<Output = ()> + 'static>(task: F) {
+    actix_rt::spawn(async move {
+        future::select(Box::pin(task), Box::pin(wait())).await;
+    });
+}
+
+/**
+ * Resolves with the name of the first SIGTERM, SIGINT or SIGQUIT received
+ **/
+pub async fn signalled() -> &'static str {
+    let kinds = vec![
+        (SignalKind::terminate(), "SIGTERM"),
+        (SignalKind::interrupt(), "SIGINT"),
+        (SignalKind::quit(), "SIGQUIT"),
+    ];
+
+    let mut streams = vec![];
+    for (kind, name) in kinds {
+        match signal(kind) {
+            Ok(stream) => streams.push((stream, name)),
+            Err(err) => warn!("Could not listen for {}: {}", name, err),
+        }
+    }
+    if streams.is_empty() {
+        return future::pending().await;
+    }
+
+    let waits = streams.iter_mut().map(|(stream, name)| {
+        let name: &'static str = name;
+        Box::pin(stream.recv().map(move |_| name))
+    });
+    let (name, ..) = future::select_all(waits).await;
+
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use actix_rt::time::delay_for;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn trigger_stops_background_tasks() {
+        let ticks = Rc::new(Cell::new(0));
+        let counter = ticks.clone();
+        spawn(async move {
+            loop {
+                counter.set(counter.get() + 1);
+                delay_for(Duration::from_millis(5)).await;
+            }
+        });
+
+        delay_for(Duration::from_millis(20)).await;
+        trigger();
+        wait().await;
+        assert!(is_triggered());
+
+        delay_for(Duration::from_millis(5)).await;
+        let stopped_at = ticks.get();
+        delay_for(Duration::from_millis(20)).await;
+
+        assert!(stopped_at > 0);
+        assert_eq!(ticks.get(), stopped_at);
+    }
+}
@@ -0,0 +1,172 @@
+//! Waiting for the dependencies before the server starts
+//!
+//! Each dependency is retried with exponential backoff until it answers or
+//! `STARTUP_DEADLINE` passes. What happens then depends on its policy: a
+//! required dependency stops the startup with an error, a degraded one lets
+//! the server start and recover in the background.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_rt::time::delay_for;
+
+use crate::config::{DependencyPolicy, StartupConfig};
+
+/// A required dependency that did not come up in time
+#[derive(Debug, PartialEq)]
+pub struct StartupError {
+    pub dependency: &'static str,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+impl Display for StartupError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{} is required but still unavailable after {} attempt(s): {}",
+            self.dependency, self.attempts, self.last_error
+        )
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// Delays between attempts: doubling from `initial`, capped at `max`
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self { Backoff { next: initial.min(max), max } }
+}
+
+impl Iterator for Backoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+
+        Some(delay)
+    }
+}
+
+/**
+ * Retry `attempt` until it succeeds or the deadline passes
+ *
+ * Ok(true) when the dependency is up, Ok(false) when it is still down but
+ * `policy` lets the server start degraded.
+ **/
+pub async fn wait_for<F, Fut, E>(
+    dependency: &'static str,
+    policy: DependencyPolicy,
+    startup: &StartupConfig,
+    mut attempt: F,
+) -> Result<bool, StartupError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let deadline = Instant::now() + Duration::from_secs(startup.deadline);
+    let mut backoff = Backoff::new(
+        Duration::from_millis(startup.backoff_initial),
+        Duration::from_millis(startup.backoff_max),
+    );
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let last_error = match attempt().await {
+            Ok(()) => {
+                if attempts > 1 {
+                    info!("{} is up after {} attempts", dependency, attempts);
+                }
+                return Ok(true);
+            },
+            Err(err) => err.to_string(),
+        };
+
+        let now = Instant::now();
+        if now >= deadline {
+            return match policy {
+                DependencyPolicy::Required => Err(StartupError {
+                    dependency,
+                    attempts,
+                    last_error,
+                }),
+                DependencyPolicy::Degraded => {
+                    warn!(
+                        "{} is still unavailable after {} attempt(s), starting degraded: {}",
+                        dependency, attempts, last_error
+                    );
+                    Ok(false)
+                },
+            };
+        }
+
+        let delay = backoff.next().unwrap_or_default().min(deadline - now);
+        warn!(
+            "{} is unavailable (attempt {}), retrying in {}ms: {}",
+            dependency,
+            attempts,
+            delay.as_millis(),
+            last_error
+        );
+        delay_for(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn startup(deadline: u64) -> StartupConfig {
+        StartupConfig {
+            deadline,
+            backoff_initial: 1,
+            backoff_max: 4,
+            redis: DependencyPolicy::Required,
+            iam: DependencyPolicy::Degraded,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let delays: Vec<u128> = Backoff::new(Duration::from_millis(100), Duration::from_millis(500))
+            .take(5)
+            .map(|delay| delay.as_millis())
+            .collect();
+
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+    }
+
+    #[actix_rt::test]
+    async fn retries_until_the_dependency_answers() {
+        let calls = Cell::new(0);
+        let up = wait_for("redis", DependencyPolicy::Required, &startup(5), || {
+            calls.set(calls.get() + 1);
+            let result = if calls.get() < 3 { Err("connection refused") } else { Ok(()) };
+            async move { result }
+        })
+        .await;
+
+        assert_eq!(up, Ok(true));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn policy_decides_after_the_deadline() {
+        let down = || async { Err::<(), _>("connection refused") };
+
+        assert_eq!(wait_for("iam", DependencyPolicy::Degraded, &startup(0), down).await, Ok(false));
+
+        let err = wait_for("redis", DependencyPolicy::Required, &startup(0), down).await.unwrap_err();
+        assert_eq!(err.to_string(), "redis is required but still unavailable after 1 attempt(s): connection refused");
+    }
+}
@@ -0,0 +1,134 @@
+//! HTTPS termination for the `TLS_*` settings
+//!
+//! Client certificates are requested but not required at the handshake, so
+//! user traffic keeps working without one. When TLS_CLIENT_CA_PATH is set a
+//! verified certificate is stored as `PeerCertificate` in the request
+//! extensions, and BeforeAction requires it on service-role requests.
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+#[cfg(feature = "openssl")]
+pub use self::acceptor::{acceptor, on_connect};
+
+/// Subject of the client certificate that was verified during the handshake
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerCertificate {
+    pub subject: String,
+}
+
+/// Port the HTTPS listener is bound to, used by the redirect listener
+#[derive(Clone, Copy, Debug)]
+pub struct HttpsPort(pub u16);
+
+/**
+ * The https:// url for `host` (with or without a port) and the request path
+ **/
+pub fn https_url(host: &str, path_and_query: &str, port: u16) -> String {
+    // Keep IPv6 literals like [::1]:8080 intact when stripping the port
+    let hostname = match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    };
+
+    if port == 443 {
+        format!("https://{}{}", hostname, path_and_query)
+    } else {
+        format!("https://{}:{}{}", hostname, port, path_and_query)
+    }
+}
+
+/**
+ * Default service of the plain HTTP listener, permanently redirects to HTTPS
+ **/
+pub async fn redirect_to_https(req: HttpRequest, port: web::Data<HttpsPort>) -> HttpResponse {
+    let path_and_query = req.uri().path_and_query().map(|value| value.as_str()).unwrap_or("/");
+    let location = https_url(req.connection_info().host(), path_and_query, port.0);
+
+    // 308 keeps the method and body, unlike 301
+    HttpResponse::PermanentRedirect()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+#[cfg(feature = "openssl")]
+mod acceptor {
+    use std::any::Any;
+    use std::io;
+
+    use actix_tls::openssl::SslStream;
+    use actix_web::dev::Extensions;
+    use actix_web::rt::net::TcpStream;
+    use openssl::error::ErrorStack;
+    use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+    use openssl::x509::{X509NameRef, X509};
+
+    use super::PeerCertificate;
+    use crate::config::TlsConfig;
+
+    fn to_io(err: ErrorStack) -> io::Error { io::Error::new(io::ErrorKind::InvalidInput, err) }
+
+    /**
+     * SslAcceptor for the configured certificate, key and optional client CA
+     **/
+    pub fn acceptor(tls: &TlsConfig) -> io::Result<SslAcceptorBuilder> {
+        let (cert, key) = match (&tls.cert_path, &tls.key_path) {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS_CERT_PATH and TLS_KEY_PATH are required")),
+        };
+
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(to_io)?;
+        builder.set_certificate_chain_file(cert).map_err(to_io)?;
+        builder.set_private_key_file(key, SslFiletype::PEM).map_err(to_io)?;
+        builder.check_private_key().map_err(to_io)?;
+
+        if let Some(ca) = &tls.client_ca_path {
+            let pem = std::fs::read(ca)?;
+            for cert in X509::stack_from_pem(&pem).map_err(to_io)? {
+                builder.add_client_ca(&cert).map_err(to_io)?;
+                builder.cert_store_mut().add_cert(cert).map_err(to_io)?;
+            }
+            // Certificates that are sent must verify, but sending one is optional
+            builder.set_verify(SslVerifyMode::PEER);
+        }
+
+        Ok(builder)
+    }
+
+    fn subject(name: &X509NameRef) -> String {
+        name.entries()
+            .map(|entry| {
+                let field = entry.object().nid().short_name().unwrap_or("?");
+                let value = entry.data().as_utf8().map(|value| value.to_string()).unwrap_or_default();
+                format!("{}={}", field, value)
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /**
+     * HttpServer::on_connect callback, keeps the verified client certificate for BeforeAction
+     **/
+    pub fn on_connect(connection: &dyn Any, extensions: &mut Extensions) {
+        if let Some(stream) = connection.downcast_ref::<SslStream<TcpStream>>() {
+            if let Some(cert) = stream.ssl().peer_certificate() {
+                extensions.insert(PeerCertificate {
+                    subject: subject(cert.subject_name()),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_https_urls() {
+        assert_eq!(https_url("example.com", "/a?b=1", 443), "https://example.com/a?b=1");
+        assert_eq!(https_url("example.com:8080", "/", 8443), "https://example.com:8443/");
+        assert_eq!(https_url("[::1]:8080", "/", 8443), "https://[::1]:8443/");
+        assert_eq!(https_url("[::1]", "/", 443), "https://[::1]/");
+    }
+}
@@ -0,0 +1,311 @@
+//! Typed caching on top of `CacheBackend`
+//!
+//! A `Cache<K, V>` keeps values of one kind under a `Namespace`, which gives
+//! the key prefix, the TTL and the metrics label, and encodes them with a
+//! `Codec`, JSON by default. `get_or_load` and `get_many_or_load` only call the
+//! loader for the keys the cache misses and store what it returns, so a new
+//! entity only needs a namespace and a loader.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::components::cache::CacheBackend;
+use crate::components::metrics;
+use crate::config::{Config, CONFIG};
+use crate::errors::AppError;
+
+/// Where the values of one kind live in the cache, and for how long
+pub struct Namespace {
+    /// Key prefix, keys are `{name}:{key}`
+    pub name: &'static str,
+    /// The `cache` label of `cache_requests_total`
+    pub metric: &'static str,
+    /// Seconds to keep values, read on every write so config reloads apply; 0 keeps them forever
+    pub ttl: fn(&Config) -> usize,
+}
+
+/// Turns values into the strings the cache stores, and back
+pub trait Codec<V> {
+    fn encode(&self, value: &V) -> Result<String, AppError>;
+
+    fn decode(&self, raw: &str) -> Result<V, AppError>;
+}
+
+/// serde_json, the format every cached value has used so far
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl<V: Serialize + DeserializeOwned> Codec<V> for JsonCodec {
+    fn encode(&self, value: &V) -> Result<String, AppError> { Ok(serde_json::to_string(value)?) }
+
+    fn decode(&self, raw: &str) -> Result<V, AppError> { Ok(serde_json::from_str(raw)?) }
+}
+
+/// Values of type `V` under `namespace`, looked up by `K`
+pub struct Cache<'a, K, V, C = JsonCodec> {
+    backend: &'a dyn CacheBackend,
+    namespace: &'a Namespace,
+    codec: C,
+    kind: PhantomData<fn(&K) -> V>,
+}
+
+impl<'a, K, V> Cache<'a, K, V, JsonCodec> {
+    pub fn new(backend: &'a dyn CacheBackend, namespace: &'a Namespace) -> Self {
+        Cache {
+            backend,
+            namespace,
+            codec: JsonCodec,
+            kind: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V, C> Cache<'a, K, V, C>
+where
+    K: Display + Eq + Hash + Clone,
+    C: Codec<V>,
+{
+    #[allow(unused)]
+    pub fn with_codec<D: Codec<V>>(self, codec: D) -> Cache<'a, K, V, D> {
+        Cache {
+            backend: self.backend,
+            namespace: self.namespace,
+            codec,
+            kind: PhantomData,
+        }
+    }
+
+    fn key(&self, key: &K) -> String { format!("{}:{}", self.namespace.name, key) }
+
+    /**
+     * The cached value; a value that no longer decodes reads as a miss
+     **/
+    fn decode(&self, key: &K, raw: Option<String>) -> Option<V> {
+        let raw = raw?;
+
+        match self.codec.decode(&raw) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                debug!("Ignoring the cached {}: {}", self.key(key), err);
+                None
+            },
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Result<Option<V>, AppError> {
+        let value = match self.backend.get(&self.key(key)).await {
+            Ok(raw) => self.decode(key, raw),
+            Err(err) => {
+                metrics::cache_lookup(self.namespace.metric, 0, 1);
+                return Err(err);
+            },
+        };
+        metrics::cache_lookup(self.namespace.metric, value.is_some() as usize, value.is_none() as usize);
+
+        Ok(value)
+    }
+
+    /**
+     * One entry per key, in order
+     **/
+    pub async fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, AppError> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let raw = match self.backend.mget(&keys.iter().map(|key| self.key(key)).collect::<Vec<_>>()).await {
+            Ok(raw) => raw,
+            Err(err) => {
+                metrics::cache_lookup(self.namespace.metric, 0, keys.len());
+                return Err(err);
+            },
+        };
+
+        let values: Vec<Option<V>> = keys.iter().zip(raw).map(|(key, raw)| self.decode(key, raw)).collect();
+        let hits = values.iter().filter(|value| value.is_some()).count();
+        metrics::cache_lookup(self.namespace.metric, hits, values.len() - hits);
+
+        Ok(values)
+    }
+
+    /**
+     * Store `value` for the namespace TTL, false when it could not be
+     **/
+    pub async fn set(&self, key: &K, value: &V) -> bool {
+        let raw = match self.codec.encode(value) {
+            Ok(raw) => raw,
+            Err(err) => {
+                warn!("Could not encode {} for the cache: {}", self.key(key), err);
+                return false;
+            },
+        };
+
+        self.backend.set(&self.key(key), &raw, (self.namespace.ttl)(&CONFIG.get())).await
+    }
+
+    /**
+     * Store every entry, returning how many were stored
+     **/
+    pub async fn set_many(&self, entries: &[(K, V)]) -> usize {
+        let mut stored = 0;
+        for (key, value) in entries {
+            stored += self.set(key, value).await as usize;
+        }
+
+        stored
+    }
+
+    /**
+     * Delete the entries of `keys`, returning how many existed
+     **/
+    #[allow(unused)]
+    pub async fn invalidate(&self, keys: &[K]) -> Result<usize, AppError> {
+        self.backend.del_many(&keys.iter().map(|key| self.key(key)).collect::<Vec<_>>()).await
+    }
+
+    /**
+     * The cached value, or the one `load` returns, which is then cached. The
+     * cache being down only costs the lookup, loader errors are returned.
+     **/
+    pub async fn get_or_load<F, Fut>(&self, key: &K, load: F) -> Result<V, AppError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, AppError>>,
+    {
+        match self.get(key).await {
+            Ok(Some(value)) => return Ok(value),
+            Ok(None) => {},
+            Err(err) => debug!("Loading {} without the cache: {}", self.key(key), err),
+        }
+
+        let value = load().await?;
+        self.set(key, &value).await;
+
+        Ok(value)
+    }
+
+    /**
+     * The values of `keys`, `load` getting the missed keys and returning the
+     * entries it found, which are then cached. Keys found nowhere are left
+     * out, as are the missed keys when `load` fails.
+     **/
+    pub async fn get_many_or_load<F, Fut>(&self, keys: &[K], load: F) -> Result<HashMap<K, V>, AppError>
+    where
+        F: FnOnce(Vec<K>) -> Fut,
+        Fut: Future<Output = Result<Vec<(K, V)>, AppError>>,
+    {
+        let cached = match self.get_many(keys).await {
+            Ok(cached) => cached,
+            Err(err) => {
+                debug!("Loading {} {} key(s) without the cache: {}", keys.len(), self.namespace.name, err);
+                keys.iter().map(|_| None).collect()
+            },
+        };
+
+        let mut found = HashMap::with_capacity(keys.len());
+        let mut missed = vec![];
+        for (key, value) in keys.iter().zip(cached) {
+            match value {
+                Some(value) => {
+                    found.insert(key.clone(), value);
+                },
+                None if !missed.contains(key) => missed.push(key.clone()),
+                None => {},
+            }
+        }
+        if missed.is_empty() {
+            return Ok(found);
+        }
+
+        // The cached values still answer the request when the loader fails
+        let loaded = match load(missed).await {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                warn!("Returning {} cached {} without the missed keys: {}", found.len(), self.namespace.name, err);
+                return Ok(found);
+            },
+        };
+        self.set_many(&loaded).await;
+        found.extend(loaded);
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::cache::MemoryCache;
+    use std::cell::RefCell;
+
+    const SQUARES: Namespace = Namespace {
+        name: "Square",
+        metric: "square",
+        ttl: |_| 0,
+    };
+
+    /// Stores numbers in hex, to check the codec is the one used both ways
+    struct HexCodec;
+
+    impl Codec<u64> for HexCodec {
+        fn encode(&self, value: &u64) -> Result<String, AppError> { Ok(format!("{:x}", value)) }
+
+        fn decode(&self, raw: &str) -> Result<u64, AppError> {
+            u64::from_str_radix(raw, 16).map_err(|err| AppError::Deserialize(err.to_string()))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn loads_only_what_the_cache_misses() {
+        let backend = MemoryCache::new();
+        let cache: Cache<u64, u64> = Cache::new(&backend, &SQUARES);
+        let asked = RefCell::new(vec![]);
+        let load = |keys: Vec<u64>| {
+            asked.borrow_mut().push(keys.clone());
+            // 7 is unknown to the loader
+            async move { Ok(keys.into_iter().filter(|key| *key != 7).map(|key| (key, key * key)).collect()) }
+        };
+
+        assert_eq!(cache.get_or_load(&3, || async { Ok(9) }).await.unwrap(), 9);
+        assert_eq!(backend.get("Square:3").await.unwrap().as_deref(), Some("9"));
+
+        let values = cache.get_many_or_load(&[3, 4, 4, 7], load).await.unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[&4], 16);
+        assert_eq!(cache.get_many_or_load(&[3, 4, 7], load).await.unwrap().len(), 2);
+        assert_eq!(*asked.borrow(), vec![vec![4, 7], vec![7]]);
+
+        assert_eq!(cache.invalidate(&[3, 4]).await.unwrap(), 2);
+        assert_eq!(cache.get(&3).await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn returns_the_cached_values_when_the_loader_fails() {
+        let backend = MemoryCache::new();
+        let cache: Cache<u64, u64> = Cache::new(&backend, &SQUARES);
+        cache.set(&2, &4).await;
+        let failing = |_: Vec<u64>| async { Err::<Vec<(u64, u64)>, _>(AppError::Internal("user-core is down".into())) };
+
+        let values = cache.get_many_or_load(&[2, 3], failing).await.unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[&2], 4);
+        assert!(cache.get_many_or_load(&[3], failing).await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn encodes_with_the_codec_and_ignores_what_does_not_decode() {
+        let backend = MemoryCache::new();
+        let cache = Cache::<u64, u64>::new(&backend, &SQUARES).with_codec(HexCodec);
+
+        assert_eq!(cache.set_many(&[(1, 255), (2, 16)]).await, 2);
+        assert_eq!(backend.get("Square:1").await.unwrap().as_deref(), Some("ff"));
+        backend.set("Square:2", "not hex", 0).await;
+
+        assert_eq!(cache.get_many(&[1, 2, 3]).await.unwrap(), vec![Some(255), None, None]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Where the service runs, parsed from ENV
+///
+/// Each environment comes with a behaviour profile that provides the defaults
+/// for LOG_FORMAT, SENTRY_DEBUG and ERROR_EXPOSE_CAUSE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    Dev,
+    Test,
+    Staging,
+    Production,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+/// Per environment defaults
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    pub log_format: LogFormat,
+    pub sentry_debug: bool,
+    pub expose_error_cause: bool,
+}
+
+impl Environment {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Environment::Dev => "dev",
+            Environment::Test => "test",
+            Environment::Staging => "staging",
+            Environment::Production => "production",
+        }
+    }
+
+    pub fn profile(self) -> Profile {
+        match self {
+            Environment::Dev => Profile {
+                log_format: LogFormat::Pretty,
+                sentry_debug: true,
+                expose_error_cause: true,
+            },
+            Environment::Test => Profile {
+                log_format: LogFormat::Pretty,
+                sentry_debug: false,
+                expose_error_cause: true,
+            },
+            Environment::Staging => Profile {
+                log_format: LogFormat::Json,
+                sentry_debug: false,
+                expose_error_cause: true,
+            },
+            Environment::Production => Profile {
+                log_format: LogFormat::Json,
+                sentry_debug: false,
+                expose_error_cause: false,
+            },
+        }
+    }
+}
+
+impl FromStr for Environment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "dev" | "development" | "local" => Ok(Environment::Dev),
+            "test" | "testing" => Ok(Environment::Test),
+            "staging" | "stg" => Ok(Environment::Staging),
+            "production" | "prod" => Ok(Environment::Production),
+            _ => Err("expected one of dev, test, staging, production".to_string()),
+        }
+    }
+}
+
+impl Display for Environment {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult { write!(f, "{}", self.as_str()) }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "pretty" | "text" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected pretty or json".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_names_only() {
+        assert_eq!("prod".parse::<Environment>(), Ok(Environment::Production));
+        assert_eq!("Staging".parse::<Environment>(), Ok(Environment::Staging));
+        assert_eq!("development".parse::<Environment>(), Ok(Environment::Dev));
+        assert!("prodution".parse::<Environment>().is_err());
+    }
+
+    #[test]
+    fn only_production_hides_error_causes() {
+        assert!(Environment::Staging.profile().expose_error_cause);
+        assert!(!Environment::Production.profile().expose_error_cause);
+        assert!(Environment::Dev.profile().sentry_debug);
+        assert_eq!(Environment::Production.profile().log_format, LogFormat::Json);
+    }
+}
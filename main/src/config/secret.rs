@@ -0,0 +1,53 @@
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub const REDACTED: &str = "***";
+
+/// A setting that must never be printed: Debug, Display and Serialize all
+/// show `***`, the value is only reachable through `expose`
+#[derive(Clone, Default, PartialEq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self { Secret(value) }
+
+    pub fn expose(&self) -> &T { &self.0 }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self { Secret(value) }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult { write!(f, "Secret({})", REDACTED) }
+}
+
+impl<T> Display for Secret<T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult { write!(f, "{}", REDACTED) }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> { serializer.serialize_str(REDACTED) }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_prints_the_value() {
+        let secret = Secret::new("redis://:password@localhost:6379".to_string());
+
+        assert_eq!(format!("{:?}", secret), "Secret(***)");
+        assert_eq!(secret.to_string(), "***");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"***\"");
+        assert_eq!(secret.expose(), "redis://:password@localhost:6379");
+    }
+}
@@ -0,0 +1,269 @@
+//! Configuration layers
+//!
+//! Values are looked up from the highest layer down: command line overrides,
+//! process env vars, the .env file, the optional per-environment TOML/YAML
+//! file and finally the built-in defaults. Every value remembers the layer it
+//! came from so a deployment can be inspected without guessing.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::{ConfigIssue, Environment};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "path", rename_all = "lowercase")]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    DotEnv(PathBuf),
+    Env,
+    Cli,
+    /// Read from the file named by a `<KEY>_FILE` setting
+    SecretFile(PathBuf),
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "file {}", path.display()),
+            ConfigSource::DotEnv(path) => write!(f, "dotenv {}", path.display()),
+            ConfigSource::Env => write!(f, "env"),
+            ConfigSource::Cli => write!(f, "cli"),
+            ConfigSource::SecretFile(path) => write!(f, "secret file {}", path.display()),
+        }
+    }
+}
+
+/// Settings given on the command line
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub config_file: Option<PathBuf>,
+    pub values: Vec<(String, String)>,
+}
+
+fn cli_issue(value: &str, reason: &str) -> ConfigIssue {
+    ConfigIssue::Invalid {
+        key: "argv",
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Parse a `KEY=VALUE` command line override
+pub fn parse_pair(pair: &str) -> Result<(String, String), ConfigIssue> {
+    match pair.find('=') {
+        Some(pos) if pos > 0 => Ok((pair[..pos].trim().to_uppercase(), pair[pos + 1..].to_string())),
+        _ => Err(cli_issue(pair, "expected KEY=VALUE")),
+    }
+}
+
+/// Every layer, lowest precedence first
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSources {
+    layers: Vec<(ConfigSource, HashMap<String, String>)>,
+}
+
+impl ConfigSources {
+    pub fn new() -> Self { ConfigSources::default() }
+
+    /// Add a layer that wins over every layer added before it
+    pub fn with_layer(mut self, source: ConfigSource, values: HashMap<String, String>) -> Self {
+        self.layers.push((source, values));
+        self
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<(String, ConfigSource)> {
+        self.lookup_ranked(key).map(|(_, value, source)| (value, source))
+    }
+
+    /// Like `lookup`, also returning the layer's precedence (higher wins)
+    pub fn lookup_ranked(&self, key: &str) -> Option<(usize, String, ConfigSource)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(rank, (source, values))| values.get(key).map(|value| (rank, value.clone(), source.clone())))
+    }
+
+    /// The files behind the file and .env layers
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.layers
+            .iter()
+            .filter_map(|(source, _)| match source {
+                ConfigSource::File(path) | ConfigSource::DotEnv(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Assemble the standard layers: file, .env, env and command line
+    ///
+    /// Problems with the file layer are returned alongside the sources so
+    /// they are reported together with the rest of the configuration.
+    pub fn load(dotenv_name: &str, overrides: &Overrides) -> (ConfigSources, Vec<ConfigIssue>) {
+        let mut upper = ConfigSources::new();
+        if let Some(path) = find_upwards(dotenv_name) {
+            let values = read_dotenv(&path);
+            upper = upper.with_layer(ConfigSource::DotEnv(path), values);
+        }
+        upper = upper
+            .with_layer(ConfigSource::Env, env::vars().collect())
+            .with_layer(ConfigSource::Cli, overrides.values.iter().cloned().collect());
+
+        // The file is picked per environment, so ENV must be known before it is read
+        let file = overrides
+            .config_file
+            .clone()
+            .or_else(|| upper.lookup("CONFIG_FILE").map(|(path, _)| PathBuf::from(path)))
+            .or_else(|| {
+                let env = upper.lookup("ENV").and_then(|(env, _)| env.trim().parse().ok());
+                default_file(env.unwrap_or(Environment::Dev))
+            });
+
+        let mut issues = vec![];
+        let mut sources = ConfigSources::new();
+        if let Some(path) = file {
+            match read_file(&path) {
+                Ok(values) => sources = sources.with_layer(ConfigSource::File(path), values),
+                Err(reason) => issues.push(ConfigIssue::Invalid {
+                    key: "CONFIG_FILE",
+                    value: path.display().to_string(),
+                    reason,
+                }),
+            }
+        }
+        sources.layers.extend(upper.layers);
+
+        (sources, issues)
+    }
+}
+
+/// `config/<env>.toml`, `config/<env>.yaml` or `config/<env>.yml`, if present
+fn default_file(env: Environment) -> Option<PathBuf> {
+    ["toml", "yaml", "yml"]
+        .iter()
+        .map(|ext| PathBuf::from("config").join(format!("{}.{}", env, ext)))
+        .find(|path| path.is_file())
+}
+
+fn find_upwards(name: &str) -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+
+    dir.ancestors().map(|dir| dir.join(name)).find(|path| path.is_file())
+}
+
+// from_path_iter is the only dotenv api that reads the file without writing
+// into the process env, which would hide where a value came from
+#[allow(deprecated)]
+fn read_dotenv(path: &Path) -> HashMap<String, String> {
+    match dotenv::from_path_iter(path) {
+        Ok(iter) => iter.filter_map(|item| item.ok()).collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Read a TOML or YAML file and flatten it to env style keys, so
+/// `[redis] uri = ".."` becomes `REDIS_URI`
+fn read_file(path: &Path) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+    let value: serde_json::Value = match extension {
+        "toml" => toml::from_str(&content).map_err(|err| err.to_string())?,
+        "yaml" | "yml" => serde_yaml::from_str(&content).map_err(|err| err.to_string())?,
+        _ => return Err("expected a .toml, .yaml or .yml file".to_string()),
+    };
+
+    let mut values = HashMap::new();
+    flatten("", &value, &mut values);
+
+    Ok(values)
+}
+
+fn flatten(prefix: &str, value: &serde_json::Value, out: &mut HashMap<String, String>) {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.to_uppercase().replace('-', "_");
+                let key = if prefix.is_empty() { key } else { format!("{}_{}", prefix, key) };
+                flatten(&key, value, out);
+            }
+        },
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(scalar_to_string).collect();
+            out.insert(prefix.to_string(), items.join(","));
+        },
+        Value::Null => {},
+        _ => {
+            out.insert(prefix.to_string(), scalar_to_string(value));
+        },
+    }
+}
+
+fn scalar_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_layers_win() {
+        let mut file = HashMap::new();
+        file.insert("WORKER".to_string(), "4".to_string());
+        file.insert("ENV".to_string(), "staging".to_string());
+        let mut env = HashMap::new();
+        env.insert("WORKER".to_string(), "8".to_string());
+
+        let sources = ConfigSources::new()
+            .with_layer(ConfigSource::File(PathBuf::from("config/staging.toml")), file)
+            .with_layer(ConfigSource::Env, env);
+
+        assert_eq!(sources.lookup("WORKER"), Some(("8".to_string(), ConfigSource::Env)));
+        assert_eq!(
+            sources.lookup("ENV"),
+            Some(("staging".to_string(), ConfigSource::File(PathBuf::from("config/staging.toml"))))
+        );
+        assert_eq!(sources.lookup("SERVER"), None);
+    }
+
+    #[test]
+    fn flattens_nested_tables() {
+        let value: serde_json::Value = toml::from_str(
+            r#"
+            worker = 4
+            [redis]
+            uri = "redis://localhost:6379/1"
+            [rate-limit]
+            detect_duplicate_time = 5
+            "#,
+        )
+        .unwrap();
+        let mut values = HashMap::new();
+        flatten("", &value, &mut values);
+
+        assert_eq!(values["WORKER"], "4");
+        assert_eq!(values["REDIS_URI"], "redis://localhost:6379/1");
+        assert_eq!(values["RATE_LIMIT_DETECT_DUPLICATE_TIME"], "5");
+    }
+
+    #[test]
+    fn parses_command_line_overrides() {
+        assert_eq!(parse_pair("worker=4"), Ok(("WORKER".to_string(), "4".to_string())));
+        assert_eq!(parse_pair("URL=a=b"), Ok(("URL".to_string(), "a=b".to_string())));
+        assert!(parse_pair("WORKER").is_err());
+        assert!(parse_pair("=4").is_err());
+    }
+}
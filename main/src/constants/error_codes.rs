@@ -1,15 +1,84 @@
-pub struct ErrorCodes {}
+//! The error code registry, the one place codes and their default messages are defined
+//!
+//! `error_codes!` expands it into the `ErrorCodes` and `Messages` constants and
+//! the `ERROR_CODES` table served at `/errors`. A code used twice fails the
+//! build. The default messages are the vi locale; `main/locales` translates them,
+//! as well as the field violation messages defined here too.
 
-impl ErrorCodes {
-    pub const UNKNOWN: u16 = 900;
-    pub const INVALID_REQUEST: u16 = 901;
+use codegen::error_codes;
+use serde::Serialize;
+
+/// One entry of the registry
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ErrorCode {
+    pub name: &'static str,
+    pub code: u16,
+    pub message: &'static str,
+}
+
+error_codes! {
+    UNKNOWN = 900 => "Có lỗi xẩy ra mời bạn thử lại.",
+    INVALID_REQUEST = 901 => "Yêu cầu không đúng, mời bạn thử lại!",
+    VALIDATION_FAILED = 902 => "Dữ liệu không hợp lệ, mời bạn kiểm tra lại.",
+    NOT_FOUND = 904 => "Không tìm thấy dữ liệu.",
     // General error
-    pub const SYSTEM_GENERAL_ERROR: u16 = 1000;
+    SYSTEM_GENERAL_ERROR = 1000 => "Có lỗi xẩy ra mời bạn thử lại.",
     // System, User 10xx
-    pub const GAPO_ROLE_NOT_SUPPORT: u16 = 1001;
-    pub const USER_NOT_PERMISSION: u16 = 1002;
-    pub const USER_NOT_EXIST_OR_IS_BLOCKING: u16 = 1003;
-    // pub const PAGE_NOT_EXIST_OR_IS_BLOCKING: u16 = 1004;
-    // pub const HASHTAG_NOT_EXIST_OR_IS_BLOCKING: u16 = 1008;
-    // pub const GROUP_NOT_EXIST_OR_IS_BLOCKING: u16 = 1009;
+    GAPO_ROLE_NOT_SUPPORT = 1001 => "Vai trò của bạn không được hỗ trợ.",
+    USER_NOT_PERMISSION = 1002 => "Bạn không có quyền thực hiện chức năng này.",
+    /// `{id}`: the unknown user ids
+    USER_NOT_EXIST_OR_IS_BLOCKING = 1003 => "Người dùng {id} không tồn tại hoặc bị khóa",
+    // PAGE_NOT_EXIST_OR_IS_BLOCKING = 1004
+    // HASHTAG_NOT_EXIST_OR_IS_BLOCKING = 1008
+    // GROUP_NOT_EXIST_OR_IS_BLOCKING = 1009
+    // Cache and other services 11xx
+    CACHE_UNAVAILABLE = 1101 => "Hệ thống đang bận, mời bạn thử lại sau.",
+    CACHE_ERROR = 1102 => "Có lỗi xẩy ra mời bạn thử lại.",
+    UPSTREAM_TIMEOUT = 1103 => "Hệ thống đang bận, mời bạn thử lại sau.",
+    UPSTREAM_UNAVAILABLE = 1104 => "Hệ thống đang bận, mời bạn thử lại sau.",
+    UPSTREAM_BAD_STATUS = 1105 => "Có lỗi xẩy ra mời bạn thử lại.",
+    INVALID_PAYLOAD = 1106 => "Có lỗi xẩy ra mời bạn thử lại.",
+}
+
+/// What is wrong with one input field, see `FieldViolation`
+pub struct ViolationCodes {}
+
+impl ViolationCodes {
+    pub const REQUIRED: &'static str = "required";
+    pub const UNKNOWN_FIELD: &'static str = "unknown_field";
+    pub const INVALID_TYPE: &'static str = "invalid_type";
+    pub const INVALID_VALUE: &'static str = "invalid_value";
+    /// The input could not be parsed at all
+    pub const MALFORMED: &'static str = "malformed";
+    pub const TOO_LARGE: &'static str = "too_large";
+}
+
+/// Every violation code with its default (vi) message, `{field}` is the field path
+pub const VIOLATIONS: &[(&str, &str)] = &[
+    (ViolationCodes::REQUIRED, "Thiếu trường {field}."),
+    (ViolationCodes::UNKNOWN_FIELD, "Trường {field} không được hỗ trợ."),
+    (ViolationCodes::INVALID_TYPE, "Trường {field} sai kiểu dữ liệu."),
+    (ViolationCodes::INVALID_VALUE, "Giá trị của {field} không hợp lệ."),
+    (ViolationCodes::MALFORMED, "Không đọc được {field}."),
+    (ViolationCodes::TOO_LARGE, "{field} quá lớn."),
+];
+
+impl ErrorCodes {
+    /**
+     * The registry entry of `code`
+     **/
+    pub fn find(code: u16) -> Option<&'static ErrorCode> { ERROR_CODES.iter().find(|entry| entry.code == code) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constants_and_table_agree() {
+        assert_eq!(ErrorCodes::USER_NOT_PERMISSION, 1002);
+        assert_eq!(Messages::USER_NOT_PERMISSION, ErrorCodes::find(1002).unwrap().message);
+        assert_eq!(ErrorCodes::find(1001).unwrap().name, "GAPO_ROLE_NOT_SUPPORT");
+        assert!(ErrorCodes::find(1).is_none());
+    }
 }
@@ -1,2 +1 @@
 pub(crate) mod error_codes;
-pub(crate) mod error_messages;
@@ -0,0 +1,89 @@
+//! Operational endpoints, only served on the admin listener (ADMIN_BIND)
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::components::cache::{self, CacheBackend};
+use crate::components::logger;
+use crate::config::CONFIG;
+use crate::errors::ApiError;
+use crate::services::iam_service::IamKeys;
+
+#[derive(Deserialize)]
+pub struct FlushPrefix {
+    pub prefix: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize)]
+pub struct LogLevel {
+    pub filter: String,
+}
+
+/// The effective config, secrets redacted, with where each value came from
+#[get("/config")]
+pub async fn config() -> impl Responder {
+    let config = CONFIG.get();
+    let sources: serde_json::Map<String, serde_json::Value> = config
+        .provenance
+        .iter()
+        .map(|(key, source)| (key.clone(), json!(source.to_string())))
+        .collect();
+
+    HttpResponse::Ok().json(json!({ "values": *config, "sources": sources }))
+}
+
+/// Reload the IAM service keys now instead of waiting for IAM_REFRESH_INTERVAL
+#[post("/iam/refresh")]
+pub async fn iam_refresh(iam_keys: Data<IamKeys>) -> Result<impl Responder, ApiError> {
+    let keys = iam_keys.load().await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "keys": keys })))
+}
+
+#[get("/cache/{key}")]
+pub async fn cache_get(cache: Data<dyn CacheBackend>, key: Path<String>) -> Result<impl Responder, ApiError> {
+    match cache.get(&key).await? {
+        Some(value) => Ok(HttpResponse::Ok().json(json!({ "key": *key, "value": value }))),
+        None => Ok(HttpResponse::NotFound().json(json!({ "key": *key, "value": null }))),
+    }
+}
+
+#[delete("/cache/{key}")]
+pub async fn cache_del(cache: Data<dyn CacheBackend>, key: Path<String>) -> Result<impl Responder, ApiError> {
+    let deleted = cache.del_many(&[key.into_inner()]).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "deleted": deleted })))
+}
+
+/// Delete every key starting with `prefix`, or only list them with `dry_run=true`
+#[post("/cache/flush-prefix")]
+pub async fn cache_flush_prefix(
+    cache: Data<dyn CacheBackend>,
+    query: Query<FlushPrefix>,
+) -> Result<impl Responder, ApiError> {
+    let FlushPrefix { prefix, dry_run } = query.into_inner();
+
+    let body = if dry_run {
+        json!({ "keys": cache.keys_with_prefix(&prefix).await? })
+    } else {
+        json!({ "deleted": cache::flush_prefix(&**cache, &prefix).await? })
+    };
+
+    Ok(HttpResponse::Ok().json(body))
+}
+
+#[get("/log-level")]
+pub async fn log_level() -> impl Responder { HttpResponse::Ok().json(json!({ "filter": logger::filter() })) }
+
+/// Replace the RUST_LOG style filter until the next restart, or until RUST_LOG itself is reloaded
+#[put("/log-level")]
+pub async fn set_log_level(body: Json<LogLevel>) -> impl Responder {
+    logger::set_filter(&body.filter);
+    info!("Log filter set to {:?} from the admin listener", body.filter);
+
+    HttpResponse::Ok().json(json!({ "filter": logger::filter() }))
+}
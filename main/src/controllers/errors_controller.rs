@@ -0,0 +1,37 @@
+use actix_web::http::header;
+use actix_web::{get, HttpRequest, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::components::i18n::CATALOGUE;
+use crate::config::CONFIG;
+use crate::constants::error_codes::{ERROR_CODES, VIOLATIONS};
+
+/// The error code registry and the field violation codes, messages in the negotiated locale, for clients to map codes
+#[get("/errors")]
+pub async fn catalogue(req: HttpRequest) -> impl Responder {
+    let locale = CATALOGUE.negotiate(req.headers(), &CONFIG.get().i18n.default_locale);
+    let no_params = Default::default();
+    let errors: Vec<_> = ERROR_CODES
+        .iter()
+        .map(|entry| {
+            let message = CATALOGUE.message(&locale, entry.code, &no_params);
+            json!({
+                "code": entry.code,
+                "name": entry.name,
+                "message": message.as_deref().unwrap_or(entry.message),
+            })
+        })
+        .collect();
+    let violations: Vec<_> = VIOLATIONS
+        .iter()
+        .map(|(code, default)| {
+            // Filling `{field}` with itself keeps the template for clients
+            let message = CATALOGUE.violation(&locale, code, "{field}");
+            json!({ "code": code, "message": message.as_deref().unwrap_or(default) })
+        })
+        .collect();
+
+    HttpResponse::Ok()
+        .header(header::CONTENT_LANGUAGE, locale.as_str())
+        .json(json!({ "locale": locale, "errors": errors, "violations": violations }))
+}
@@ -0,0 +1,25 @@
+use actix_web::web::Data;
+use actix_web::{get, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::components::cache::CacheBackend;
+use crate::config::CONFIG;
+use crate::services::health_service;
+use crate::services::iam_service::IamKeys;
+
+/// Liveness: the process is up and serving requests, no dependency is touched
+#[get("/health/live")]
+pub async fn live() -> impl Responder { HttpResponse::Ok().json(json!({ "status": "ok" })) }
+
+/// Readiness: the cache, the IAM keys and optionally user-core, 503 when any check fails
+#[get("/health/ready")]
+pub async fn ready(cache: Data<dyn CacheBackend>, iam_keys: Data<IamKeys>) -> impl Responder {
+    let config = CONFIG.get();
+    let readiness = health_service::readiness(cache, &iam_keys, &config.health).await;
+
+    if readiness.is_ready() {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
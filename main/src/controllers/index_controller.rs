@@ -1,6 +1,5 @@
-use crate::components::databases::redis_db::RedisDB;
+use crate::components::cache::CacheBackend;
 use crate::constants::error_codes::*;
-use crate::constants::error_messages::*;
 use crate::errors::*;
 use actix_web::http::header;
 use actix_web::web::Data;
@@ -8,8 +7,8 @@ use actix_web::{get, HttpRequest, HttpResponse, Responder};
 use codegen::validate_request;
 
 #[get("/")]
-pub async fn index(redis: Data<RedisDB>, req: HttpRequest) -> Result<impl Responder, ApiError> {
-    let ret = redis.get::<String>("khuyentest1".to_string());
+pub async fn index(cache: Data<dyn CacheBackend>, req: HttpRequest) -> Result<impl Responder, ApiError> {
+    let ret = cache.get("khuyentest1").await;
     // println!("ret1:{:?}", ret);
     // let ret = redis.get::<Vec<u8>>("khuyentest1".to_string());
     // println!("ret2:{:?}", ret);
@@ -26,7 +25,7 @@ pub async fn index(redis: Data<RedisDB>, req: HttpRequest) -> Result<impl Respon
 }
 
 #[get("/test")]
-pub async fn index_test(redis: Data<RedisDB>, req: HttpRequest) -> Result<impl Responder, ApiError> {
+pub async fn index_test(cache: Data<dyn CacheBackend>, req: HttpRequest) -> Result<impl Responder, ApiError> {
     // let ret = redis.get::<String>("khuyentest1".to_string());
     // println!("ret1:{:?}", ret);
     // let ret = redis.get::<Vec<u8>>("khuyentest1".to_string());
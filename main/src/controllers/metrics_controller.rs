@@ -0,0 +1,15 @@
+use std::sync::Arc;
+
+use actix_web::web::Data;
+use actix_web::{get, HttpResponse, Responder};
+
+use crate::components::cache::CacheBackend;
+use crate::components::metrics;
+
+/// Prometheus scrape endpoint
+#[get("/metrics")]
+pub async fn scrape(cache: Option<Data<dyn CacheBackend>>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(cache.as_deref().map(Arc::as_ref)))
+}
@@ -1 +1,5 @@
+pub mod admin_controller;
+pub mod errors_controller;
+pub mod health_controller;
 pub mod index_controller;
+pub mod metrics_controller;
@@ -1,6 +1,4 @@
-use crate::constants::error_codes::ErrorCodes;
-use crate::constants::error_messages::Messages;
-use crate::errors::ApiError;
+use crate::errors::AppError;
 
 pub enum XGapoRole {
     Service,
@@ -9,17 +7,11 @@ pub enum XGapoRole {
 
 impl XGapoRole {
     #[allow(unused)]
-    pub fn from_str(s: &str) -> Result<XGapoRole, ApiError> {
+    pub fn from_str(s: &str) -> Result<XGapoRole, AppError> {
         match s.to_lowercase().as_ref() {
             "service" => Result::Ok(XGapoRole::Service),
             "user" => Result::Ok(XGapoRole::User),
-            _ => Err(ApiError::new(
-                403,
-                Messages::USER_NOT_PERMISSION.to_string(),
-                ErrorCodes::GAPO_ROLE_NOT_SUPPORT,
-                Some(Messages::USER_NOT_PERMISSION.to_string()),
-                None,
-            )),
+            _ => Err(AppError::UnsupportedRole(format!("x-gapo-role {:?} is not supported", s))),
         }
     }
 }
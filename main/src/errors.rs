@@ -1,22 +1,69 @@
 // src/api_error.rs
-use actix_web::{error::ResponseError, http::StatusCode, web, Error};
-use sentry::protocol::{Event, Level};
-use sentry::{configure_scope, types::Uuid};
+use crate::components::i18n;
+use crate::config::{ErrorFormat, CONFIG};
+use crate::constants::error_codes::{ErrorCodes, Messages, ViolationCodes};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError, ResponseError};
+use actix_web::{http::header, http::StatusCode, web};
+use redis::{ErrorKind as RedisErrorKind, RedisError};
 use sentry_backtrace::Stacktrace;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, to_string_pretty};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter, Result};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The request an error happened in, redacted, see `request_info_utils`
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RequestInfo {
     pub headers: Option<HashMap<String, String>>,
     pub tags: Option<HashMap<String, String>>,
-    pub body: Option<HashMap<String, String>>,
+    pub body: Option<serde_json::Value>,
     pub uri: Option<String>,
+    #[serde(default)]
+    pub query: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// One invalid input field; its message is localized when the response is rendered
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldViolation {
+    /// The field name, or where the input was (`body`, `query`, `path`) when it is not known
+    pub field: String,
+    /// A `ViolationCodes` value
+    pub code: String,
+}
+
+impl FieldViolation {
+    pub fn new(field: &str, code: &str) -> FieldViolation {
+        FieldViolation {
+            field: field.to_string(),
+            code: code.to_string(),
+        }
+    }
+
+    /**
+     * The violation a serde error message describes, the field named in it
+     * or `location` when it names none
+     **/
+    pub fn from_serde(location: &str, message: &str) -> FieldViolation {
+        let named = |prefix: &str| {
+            message
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.split('`').next())
+                .filter(|field| !field.is_empty())
+        };
+
+        if let Some(field) = named("missing field `") {
+            FieldViolation::new(field, ViolationCodes::REQUIRED)
+        } else if let Some(field) = named("unknown field `") {
+            FieldViolation::new(field, ViolationCodes::UNKNOWN_FIELD)
+        } else if message.starts_with("invalid type") {
+            FieldViolation::new(location, ViolationCodes::INVALID_TYPE)
+        } else {
+            FieldViolation::new(location, ViolationCodes::INVALID_VALUE)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ApiError {
     pub http_code: u16,
     pub message: String,
@@ -24,6 +71,11 @@ pub struct ApiError {
     pub cause: Option<String>,
     pub backtrace: Option<Stacktrace>,
     pub info: Option<RequestInfo>,
+    /// Filled into the localized message, e.g. `{id}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<FieldViolation>,
 }
 
 impl ApiError {
@@ -42,6 +94,8 @@ impl ApiError {
             cause,
             backtrace,
             info: None,
+            params: BTreeMap::new(),
+            violations: Vec::new(),
         }
     }
 
@@ -61,6 +115,8 @@ impl ApiError {
             cause,
             backtrace,
             info,
+            params: BTreeMap::new(),
+            violations: Vec::new(),
         }
     }
 }
@@ -74,6 +130,8 @@ impl Default for ApiError {
             cause: None,
             backtrace: None,
             info: None,
+            params: BTreeMap::new(),
+            violations: Vec::new(),
         }
     }
 }
@@ -89,71 +147,477 @@ impl Display for ApiError {
     }
 }
 
+/// Everything that can go wrong, each kind mapped to an HTTP status, an
+/// `ErrorCodes` value and a message in one place. The String is the internal
+/// cause, only shown where ERROR_EXPOSE_CAUSE allows it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AppError {
+    /// No connection to the cache could be had
+    CacheUnavailable(String),
+    /// The cache rejected a command
+    Cache(String),
+    UpstreamTimeout(String),
+    /// Another service could not be reached
+    UpstreamUnavailable(String),
+    /// Another service answered with an unexpected status
+    UpstreamStatus { status: u16, cause: String },
+    /// A payload could not be decoded
+    Deserialize(String),
+    #[allow(unused)]
+    NotFound(String),
+    /// The ids of users user-core does not know
+    UserNotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// x-gapo-role and the api key were rejected, answered with a 403 INVALID_REQUEST as clients always got
+    RejectedRequest(String),
+    UnsupportedRole(String),
+    /// The request could not be read into what the handler takes
+    InvalidRequest { cause: String, violations: Vec<FieldViolation> },
+    /// The request was read but some of its fields are wrong
+    #[allow(unused)]
+    Validation { cause: String, violations: Vec<FieldViolation> },
+    Internal(String),
+}
+
+impl AppError {
+    pub fn http_code(&self) -> u16 {
+        match self {
+            AppError::InvalidRequest { .. } | AppError::Validation { .. } | AppError::UserNotFound(_) => 400,
+            AppError::Unauthorized(_) => 401,
+            AppError::Forbidden(_) | AppError::RejectedRequest(_) | AppError::UnsupportedRole(_) => 403,
+            AppError::NotFound(_) => 404,
+            AppError::Cache(_) | AppError::Deserialize(_) | AppError::Internal(_) => 500,
+            AppError::UpstreamUnavailable(_) | AppError::UpstreamStatus { .. } => 502,
+            AppError::CacheUnavailable(_) => 503,
+            AppError::UpstreamTimeout(_) => 504,
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            AppError::CacheUnavailable(_) => ErrorCodes::CACHE_UNAVAILABLE,
+            AppError::Cache(_) => ErrorCodes::CACHE_ERROR,
+            AppError::UpstreamTimeout(_) => ErrorCodes::UPSTREAM_TIMEOUT,
+            AppError::UpstreamUnavailable(_) => ErrorCodes::UPSTREAM_UNAVAILABLE,
+            AppError::UpstreamStatus { .. } => ErrorCodes::UPSTREAM_BAD_STATUS,
+            AppError::Deserialize(_) => ErrorCodes::INVALID_PAYLOAD,
+            AppError::NotFound(_) => ErrorCodes::NOT_FOUND,
+            AppError::UserNotFound(_) => ErrorCodes::USER_NOT_EXIST_OR_IS_BLOCKING,
+            AppError::Unauthorized(_) | AppError::Forbidden(_) => ErrorCodes::USER_NOT_PERMISSION,
+            AppError::UnsupportedRole(_) => ErrorCodes::GAPO_ROLE_NOT_SUPPORT,
+            AppError::InvalidRequest { .. } | AppError::RejectedRequest(_) => ErrorCodes::INVALID_REQUEST,
+            AppError::Validation { .. } => ErrorCodes::VALIDATION_FAILED,
+            AppError::Internal(_) => ErrorCodes::SYSTEM_GENERAL_ERROR,
+        }
+    }
+
+    /// What the client is told, the registry message of `code`
+    pub fn message(&self) -> &'static str {
+        ErrorCodes::find(self.code()).map_or(Messages::UNKNOWN, |entry| entry.message)
+    }
+
+    pub fn cause(&self) -> &str {
+        match self {
+            AppError::UpstreamStatus { cause, .. }
+            | AppError::InvalidRequest { cause, .. }
+            | AppError::Validation { cause, .. } => cause,
+            AppError::CacheUnavailable(cause)
+            | AppError::Cache(cause)
+            | AppError::UpstreamTimeout(cause)
+            | AppError::UpstreamUnavailable(cause)
+            | AppError::Deserialize(cause)
+            | AppError::NotFound(cause)
+            | AppError::UserNotFound(cause)
+            | AppError::Unauthorized(cause)
+            | AppError::Forbidden(cause)
+            | AppError::RejectedRequest(cause)
+            | AppError::UnsupportedRole(cause)
+            | AppError::Internal(cause) => cause,
+        }
+    }
+
+    pub fn violations(&self) -> &[FieldViolation] {
+        match self {
+            AppError::InvalidRequest { violations, .. } | AppError::Validation { violations, .. } => violations,
+            _ => &[],
+        }
+    }
+}
+
+impl AppError {
+    /// Values for the placeholders of the localized message
+    pub fn params(&self) -> BTreeMap<String, String> {
+        let mut params = BTreeMap::new();
+        if let AppError::UserNotFound(ids) = self {
+            params.insert("id".to_string(), ids.clone());
+        }
+
+        params
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            AppError::UpstreamStatus { status, cause } => write!(f, "upstream answered {}: {}", status, cause),
+            AppError::UserNotFound(ids) => write!(f, "user-core does not know user(s) {}", ids),
+            _ => write!(f, "{}", self.cause()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<AppError> for ApiError {
+    fn from(err: AppError) -> ApiError {
+        let params = err.params();
+        let message = i18n::fill(err.message(), &params);
+
+        ApiError {
+            params,
+            violations: err.violations().to_vec(),
+            ..ApiError::new(err.http_code(), message, err.code(), Some(err.to_string()), None)
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.http_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> web::HttpResponse { ApiError::from(self.clone()).error_response() }
+}
+
+impl From<RedisError> for AppError {
+    fn from(err: RedisError) -> AppError {
+        let unavailable = err.is_io_error()
+            || matches!(
+                err.kind(),
+                RedisErrorKind::BusyLoadingError
+                    | RedisErrorKind::TryAgain
+                    | RedisErrorKind::ClusterDown
+                    | RedisErrorKind::MasterDown
+            );
+
+        match err.kind() {
+            _ if unavailable => AppError::CacheUnavailable(err.to_string()),
+            // The reply did not fit the requested type, e.g. nil read as a String
+            RedisErrorKind::TypeError => AppError::Deserialize(err.to_string()),
+            _ => AppError::Cache(err.to_string()),
+        }
+    }
+}
+
+/// The pool of the blocking client could not hand out a connection in time
+#[cfg(feature = "blocking-redis")]
+impl From<r2d2::Error> for AppError {
+    fn from(err: r2d2::Error) -> AppError { AppError::CacheUnavailable(err.to_string()) }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(err: reqwest::Error) -> AppError {
+        if err.is_timeout() {
+            AppError::UpstreamTimeout(err.to_string())
+        } else if let Some(status) = err.status() {
+            AppError::UpstreamStatus {
+                status: status.as_u16(),
+                cause: err.to_string(),
+            }
+        } else if err.is_decode() {
+            AppError::Deserialize(err.to_string())
+        } else if err.is_builder() {
+            AppError::Internal(err.to_string())
+        } else {
+            AppError::UpstreamUnavailable(err.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> AppError { AppError::Deserialize(err.to_string()) }
+}
+
+/// A body the `Json` extractor could not take
+impl From<JsonPayloadError> for AppError {
+    fn from(err: JsonPayloadError) -> AppError {
+        let violation = match &err {
+            JsonPayloadError::Overflow => FieldViolation::new("body", ViolationCodes::TOO_LARGE),
+            JsonPayloadError::ContentType => FieldViolation::new("content-type", ViolationCodes::INVALID_VALUE),
+            JsonPayloadError::Deserialize(json) if json.is_data() => {
+                // serde_json appends the position to the message
+                let message = json.to_string();
+                let message = message.split(" at line ").next().unwrap_or_default();
+                FieldViolation::from_serde("body", message)
+            },
+            JsonPayloadError::Deserialize(_) | JsonPayloadError::Payload(_) => {
+                FieldViolation::new("body", ViolationCodes::MALFORMED)
+            },
+        };
+
+        AppError::InvalidRequest {
+            cause: err.to_string(),
+            violations: vec![violation],
+        }
+    }
+}
+
+/// A query string the `Query` extractor could not take
+impl From<QueryPayloadError> for AppError {
+    fn from(err: QueryPayloadError) -> AppError {
+        let QueryPayloadError::Deserialize(inner) = &err;
+
+        AppError::InvalidRequest {
+            violations: vec![FieldViolation::from_serde("query", &inner.to_string())],
+            cause: err.to_string(),
+        }
+    }
+}
+
+/// Path segments the `Path` extractor could not take
+impl From<PathError> for AppError {
+    fn from(err: PathError) -> AppError {
+        let PathError::Deserialize(inner) = &err;
+
+        AppError::InvalidRequest {
+            violations: vec![FieldViolation::from_serde("path", &inner.to_string())],
+            cause: err.to_string(),
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct AppErrorResponse {
     pub error: String,
 }
 
 impl ResponseError for ApiError {
-    // builds the actual response to send back when an error occurs
+    // builds the actual response to send back when an error occurs; the
+    // `Report` middleware sends it to Sentry with the request it belongs to
     fn error_response(&self) -> web::HttpResponse {
-        let err_json = json!({
-            "message": self.message,
-            "http_code": self.http_code,
-            "code": self.code
+        self.render(&Rendering::default())
+    }
+}
+
+/// Media type of RFC 7807 error responses
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// How one request wants its error responses written, see `Localize`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rendering {
+    pub locale: String,
+    pub format: ErrorFormat,
+    /// Path of the request, the problem `instance`
+    pub instance: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl Default for Rendering {
+    /// What the config asks for, used when no request is at hand
+    fn default() -> Rendering {
+        let config = CONFIG.get();
+
+        Rendering {
+            locale: config.i18n.default_locale.clone(),
+            format: config.error_format,
+            instance: None,
+            request_id: None,
+        }
+    }
+}
+
+/**
+ * problem+json when the Accept header names it, `default` otherwise
+ **/
+pub fn negotiate_format(headers: &header::HeaderMap, default: ErrorFormat) -> ErrorFormat {
+    let asks_for_problem = headers
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut pieces = range.split(';');
+            let media_type = pieces.next().unwrap_or("").trim();
+            let refused = pieces.any(|param| matches!(param.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+
+            media_type.eq_ignore_ascii_case(PROBLEM_JSON) && !refused
         });
 
-        self.sent_to_sentry();
+    if asks_for_problem {
+        ErrorFormat::Problem
+    } else {
+        default
+    }
+}
+
+impl ApiError {
+    /**
+     * The error response in the negotiated format, its message in the
+     * negotiated locale, falling back to `message`
+     **/
+    pub fn render(&self, rendering: &Rendering) -> web::HttpResponse {
+        let message = i18n::CATALOGUE
+            .message(&rendering.locale, self.code, &self.params)
+            .unwrap_or_else(|| self.message.clone());
+        // Internal details are only shown where the environment allows it
+        let cause = self.cause.as_ref().filter(|_| CONFIG.get().expose_error_cause);
+        let violations: Vec<_> = self
+            .violations
+            .iter()
+            .map(|violation| {
+                json!({
+                    "field": violation.field,
+                    "code": violation.code,
+                    "message": i18n::CATALOGUE
+                        .violation(&rendering.locale, &violation.code, &violation.field)
+                        .unwrap_or_default(),
+                })
+            })
+            .collect();
 
         let code = match StatusCode::from_u16(self.http_code) {
             Ok(val) => val,
             Err(_err) => StatusCode::OK,
         };
+        let mut response = web::HttpResponse::build(code);
+        response.header(header::CONTENT_LANGUAGE, rendering.locale.as_str());
+
+        match rendering.format {
+            ErrorFormat::Legacy => {
+                let mut err_json = json!({
+                    "message": message,
+                    "http_code": self.http_code,
+                    "code": self.code
+                });
+                if let Some(cause) = cause {
+                    err_json["cause"] = json!(cause);
+                }
+                if !violations.is_empty() {
+                    err_json["violations"] = json!(violations);
+                }
+
+                response.json(err_json)
+            },
+            ErrorFormat::Problem => {
+                let mut problem = json!({
+                    "type": format!("urn:gapo:error:{}", self.code),
+                    "title": message,
+                    "status": self.http_code,
+                    "code": self.code,
+                });
+                if let Some(cause) = cause {
+                    problem["detail"] = json!(cause);
+                }
+                if let Some(instance) = &rendering.instance {
+                    problem["instance"] = json!(instance);
+                }
+                if let Some(request_id) = &rendering.request_id {
+                    problem["request_id"] = json!(request_id);
+                }
+                if !violations.is_empty() {
+                    problem["violations"] = json!(violations);
+                }
+
+                response.content_type(PROBLEM_JSON).body(problem.to_string())
+            },
+        }
+    }
+
+    /**
+     * The ApiError or AppError behind an actix error, if it is one of ours
+     **/
+    pub fn from_error(err: &actix_web::Error) -> Option<ApiError> {
+        if let Some(err) = err.as_error::<AppError>() {
+            return Some(err.clone().into());
+        }
 
-        web::HttpResponse::build(code).json(err_json)
+        err.as_error::<ApiError>().cloned()
     }
 }
 
-impl ApiError {
-    fn sent_to_sentry(&self) {
-        let trace_max_level = 5;
-        if self.http_code > 400 {
-            let uuid = Uuid::new_v4();
-            let mut stacktrace = Stacktrace::default();
-
-            // Get 10 frames of end
-            if let Some(bt) = self.backtrace.clone() {
-                let len = bt.frames.len();
-                let start = {
-                    if len > trace_max_level {
-                        len - trace_max_level
-                    } else {
-                        0
-                    }                };
-
-                stacktrace.frames = bt.frames[start..len].to_vec();
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            // Add more info to log
-            if let Some(request_info) = &self.info {
-                configure_scope(move |scope| {
-                    for (key, value) in &request_info.headers.clone().unwrap() {
-                        scope.set_tag(key, value);
-                    }
+    #[test]
+    fn maps_each_kind_centrally() {
+        let err: ApiError = AppError::CacheUnavailable("pool timed out".to_string()).into();
 
-                    scope.set_tag("uri", &request_info.uri.clone().unwrap());
-                });
-            }
+        assert_eq!(err.http_code, 503);
+        assert_eq!(err.code, ErrorCodes::CACHE_UNAVAILABLE);
+        assert_eq!(err.message, Messages::CACHE_UNAVAILABLE);
+        assert_eq!(err.cause.as_deref(), Some("pool timed out"));
 
-            let event = Event {
-                event_id: uuid,
-                message: Some(self.message.clone()),
-                level: Level::Info,
-                stacktrace: Some(stacktrace),
-                ..Default::default()
-            };
+        let err = AppError::UpstreamStatus {
+            status: 503,
+            cause: "user-core".to_string(),
+        };
+        assert_eq!(err.http_code(), 502);
+        assert_eq!(err.to_string(), "upstream answered 503: user-core");
+    }
+
+    #[test]
+    fn converts_library_errors() {
+        let refused = RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        assert!(matches!(AppError::from(refused), AppError::CacheUnavailable(_)));
+
+        let nil = RedisError::from((RedisErrorKind::TypeError, "Response was of incompatible type"));
+        assert!(matches!(AppError::from(nil), AppError::Deserialize(_)));
+
+        let readonly = RedisError::from((RedisErrorKind::ResponseError, "READONLY"));
+        assert_eq!(AppError::from(readonly).http_code(), 500);
+
+        let json = serde_json::from_str::<u32>("not json").unwrap_err();
+        assert_eq!(AppError::from(json).code(), ErrorCodes::INVALID_PAYLOAD);
+    }
+
+    #[test]
+    fn extractor_errors_become_field_violations() {
+        let missing = serde_json::from_str::<BTreeMap<String, u32>>("{").unwrap_err();
+        let err = AppError::from(JsonPayloadError::Deserialize(missing));
+        assert_eq!(err.code(), ErrorCodes::INVALID_REQUEST);
+        assert_eq!(err.violations(), &[FieldViolation::new("body", ViolationCodes::MALFORMED)]);
 
-            sentry::capture_event(event.clone());
+        #[derive(Debug, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        #[allow(unused)]
+        struct Body {
+            prefix: String,
         }
+        let violation = |json: &str| {
+            let err = serde_json::from_str::<Body>(json).unwrap_err();
+            AppError::from(JsonPayloadError::Deserialize(err)).violations()[0].clone()
+        };
+        assert_eq!(violation("{}"), FieldViolation::new("prefix", ViolationCodes::REQUIRED));
+        assert_eq!(violation(r#"{"prefix": 1}"#), FieldViolation::new("body", ViolationCodes::INVALID_TYPE));
+        assert_eq!(
+            violation(r#"{"prefix": "a", "extra": 1}"#),
+            FieldViolation::new("extra", ViolationCodes::UNKNOWN_FIELD)
+        );
+
+        let parse = serde::de::Error::custom("invalid digit found in string");
+        let err = AppError::from(PathError::Deserialize(parse));
+        assert_eq!(err.http_code(), 400);
+        assert_eq!(err.violations(), &[FieldViolation::new("path", ViolationCodes::INVALID_VALUE)]);
+
+        let err: ApiError = AppError::from(JsonPayloadError::Overflow).into();
+        assert_eq!(err.violations, vec![FieldViolation::new("body", ViolationCodes::TOO_LARGE)]);
+    }
+
+    #[test]
+    fn problem_json_only_when_accepted() {
+        let accept = |value: &'static str| {
+            let mut headers = header::HeaderMap::new();
+            headers.insert(header::ACCEPT, header::HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(negotiate_format(&header::HeaderMap::new(), ErrorFormat::Legacy), ErrorFormat::Legacy);
+        assert_eq!(negotiate_format(&accept("application/json"), ErrorFormat::Problem), ErrorFormat::Problem);
+        assert_eq!(
+            negotiate_format(&accept("application/json, application/problem+json;q=0.5"), ErrorFormat::Legacy),
+            ErrorFormat::Problem
+        );
+        assert_eq!(negotiate_format(&accept("application/problem+json;q=0"), ErrorFormat::Legacy), ErrorFormat::Legacy);
     }
 }
@@ -1,9 +1,12 @@
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate log;
 
-use crate::app::{Server};
+use structopt::StructOpt;
+
+use crate::cli::{Cli, Command};
 
 mod app;
+mod cli;
 mod components;
 mod config;
 mod constants;
@@ -18,5 +21,8 @@ mod utils;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    Server::run().await
-}
\ No newline at end of file
+    let cli = Cli::from_args();
+    config::set_overrides(cli.overrides());
+
+    cli::run(cli.command.unwrap_or(Command::Serve)).await
+}
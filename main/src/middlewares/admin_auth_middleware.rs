@@ -0,0 +1,120 @@
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error};
+use futures::future::{ok, Either, Ready};
+
+use crate::config::{AdminConfig, CONFIG};
+use crate::errors::AppError;
+
+/// Guards the admin listener: with ADMIN_TOKEN set every request needs
+/// `Authorization: Bearer <token>`, without it only loopback peers get in.
+/// Replaces `BeforeAction`, the x-gapo-role headers mean nothing here.
+pub struct AdminAuth;
+
+impl<S, B> Transform<S> for AdminAuth
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AdminAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AdminAuthMiddleware { service })
+    }
+}
+
+pub struct AdminAuthMiddleware<S> {
+    service: S,
+}
+
+/// Compare without returning early, so the time taken does not leak how much of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/**
+ * Why `req` may not use the admin routes, if it may not
+ **/
+fn rejection(admin: &AdminConfig, req: &ServiceRequest) -> Option<AppError> {
+    match &admin.token {
+        Some(token) => {
+            let presented = req
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .unwrap_or("");
+
+            if constant_time_eq(presented.as_bytes(), token.expose().as_bytes()) {
+                None
+            } else {
+                Some(AppError::Unauthorized("missing or wrong admin token".to_string()))
+            }
+        },
+        None => match req.peer_addr() {
+            Some(addr) if addr.ip().is_loopback() => None,
+            _ => Some(AppError::Forbidden("admin requests without ADMIN_TOKEN must come from localhost".to_string())),
+        },
+    }
+}
+
+impl<S, B> Service for AdminAuthMiddleware<S>
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        match rejection(&CONFIG.get().admin, &req) {
+            None => Either::Left(self.service.call(req)),
+            Some(rejected) => Either::Right(ok(req.error_response(rejected))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn compares_tokens_fully() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[test]
+    fn needs_the_token_or_a_loopback_peer() {
+        let local = "127.0.0.1:40000".parse().unwrap();
+        let remote = "10.0.0.7:40000".parse().unwrap();
+        let mut admin = AdminConfig::default();
+
+        assert_eq!(rejection(&admin, &TestRequest::default().peer_addr(local).to_srv_request()), None);
+        assert_eq!(rejection(&admin, &TestRequest::default().peer_addr(remote).to_srv_request()).unwrap().http_code(), 403);
+
+        admin.token = Some("admin-token".to_string().into());
+        let with_token = |value: &str| TestRequest::default().peer_addr(local).header("authorization", value).to_srv_request();
+
+        assert_eq!(rejection(&admin, &with_token("Bearer admin-token")), None);
+        assert_eq!(rejection(&admin, &with_token("Bearer wrong")).unwrap().http_code(), 401);
+        assert_eq!(rejection(&admin, &TestRequest::default().peer_addr(local).to_srv_request()).unwrap().http_code(), 401);
+    }
+}
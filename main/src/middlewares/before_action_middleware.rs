@@ -1,4 +1,4 @@
-use std::{collections::HashMap, io, sync::Mutex, task::{Context, Poll}};
+use std::task::{Context, Poll};
 use std::cell::RefCell;
 use std::pin::Pin;
 use std::rc::Rc;
@@ -8,21 +8,15 @@ use actix_web::{
     dev::ServiceRequest,
     dev::ServiceResponse,
     Error,
-    error,
-    error::ErrorBadGateway,
-    http::header::{HeaderName, HeaderValue},
-    http::StatusCode,
-    web,
+    HttpMessage,
     web::Data,
 };
 use futures::future::{Future, ok, Ready};
-use sentry::configure_scope;
-use serde_json::json;
 
-use crate::services::iam_service::get_iam_keys_for_init;
-use crate::errors::ApiError;
-use crate::constants::error_codes::ErrorCodes;
-use crate::constants::error_messages::Messages;
+use crate::components::tls::PeerCertificate;
+use crate::config::CONFIG;
+use crate::services::iam_service::IamKeys;
+use crate::errors::{ApiError, AppError};
 use sentry_backtrace::current_stacktrace;
 use crate::utils::request_info_utils::{get_request_info_from_service_request};
 
@@ -68,70 +62,52 @@ impl<S, B> Service for BeforeActionMiddleware<S>
         self.service.poll_ready(cx)
     }
 
-    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
         let role = match req.headers().get("x-gapo-role") {
             Some(value) => value.to_str().unwrap().to_string(),
             _ => "".to_string(),
         };
 
-        // set default sentry
-        let headers = req.headers();
-        let uri = format!("{}?{}", req.path(), req.query_string());
-
-        configure_scope(move |scope| {
-            for (key, value) in headers.iter() {
-                scope.set_tag(key.as_str(), value.to_str().unwrap_or("").to_string());
-            }
-
-            scope.set_tag("uri", uri);
-        });
-
         let mut svc = self.service.clone();
 
         Box::pin(async move {
             let mut valid_request = "reject";
 
             if role == "service" {
-                let iam_keys_data = req
-                    .app_data::<Data<Mutex<HashMap<String, String>>>>()
-                    .expect("get iam key from app_data failse");
-
-                let mut iam_keys = iam_keys_data.lock().unwrap();
+                let iam_keys = req
+                    .app_data::<Data<IamKeys>>()
+                    .expect("get iam key from app_data failse")
+                    .clone();
 
                 let key = match req.headers().get("x-gapo-api-key") {
                     Some(value) => value.to_str().unwrap().to_string(),
                     _ => "".to_string(),
                 };
 
-                if key != "" {
-                    if let None = iam_keys.get(&key) {
-                        // info!("recall iam!");
-                        let hashs = get_iam_keys_for_init().await;
-
-                        if let Some(_v) = hashs.get(&key) {
-                            valid_request = "accepted";
-                        }
-
-                        for (k, v) in hashs.iter() {
-                            &iam_keys.insert(k.to_string(), v.to_string());
-                        }
-                    } else {
-                        valid_request = "accepted";
-                    }
+                if !key.is_empty() && iam_keys.verify(&key).await {
+                    valid_request = "accepted";
                 }
             } else {
                 valid_request = "accepted";
             }
 
+            // With mutual TLS on, services must also present a certificate signed by TLS_CLIENT_CA_PATH
+            let mut cause = "x-gapo-key-api, hoac x-gapo-role không đúng";
+            if role == "service"
+                && CONFIG.get().tls.client_ca_path.is_some()
+                && req.extensions().get::<PeerCertificate>().is_none()
+            {
+                valid_request = "reject";
+                cause = "service request without a client certificate";
+            }
+
             if valid_request != "accepted" {
-                return Err(ApiError {
-                    http_code: 403,
-                    message:  Messages::INVALID_REQUEST.to_string(),
-                    code: ErrorCodes::INVALID_REQUEST,
-                    cause: Some("x-gapo-key-api, hoac x-gapo-role không đúng".to_string()),
+                let err = ApiError {
                     backtrace: current_stacktrace(),
-                    info: get_request_info_from_service_request(&req)
-                }.into())
+                    info: get_request_info_from_service_request(&req),
+                    ..AppError::RejectedRequest(cause.to_string()).into()
+                };
+                return Ok(req.error_response(err));
             }
 
             let res = svc.call(req).await?;
@@ -0,0 +1,88 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error};
+use futures::future::{ok, Ready};
+use futures::{Future, FutureExt};
+
+use crate::errors::{ApiError, AppError};
+
+/// Turns a panic in a handler or an inner middleware into a 500 with
+/// SYSTEM_GENERAL_ERROR, so the worker keeps serving. The request is gone
+/// by then, so it answers with an error rather than a response, which
+/// `Localize` renders as the request asked; the Sentry
+/// panic integration has already reported the panic and its backtrace on the
+/// request's hub. Register it inside `Report`.
+pub struct CatchPanic;
+
+impl<S, B> Transform<S> for CatchPanic
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CatchPanicMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CatchPanicMiddleware { service })
+    }
+}
+
+pub struct CatchPanicMiddleware<S> {
+    service: S,
+}
+
+/**
+ * The 500 answered for a panic, logged with the panic message
+ **/
+fn panicked(path: &str, payload: Box<dyn Any + Send>) -> Error {
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_else(|| "unknown panic".to_string()),
+    };
+    error!("Request to {} panicked: {}", path, message);
+
+    ApiError::from(AppError::Internal(format!("panicked: {}", message))).into()
+}
+
+impl<S, B> Service for CatchPanicMiddleware<S>
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let path = req.path().to_string();
+        let service = &mut self.service;
+
+        // Middlewares do part of their work before returning the future
+        let fut = match panic::catch_unwind(AssertUnwindSafe(|| service.call(req))) {
+            Ok(fut) => fut,
+            Err(payload) => return Box::pin(async move { Err(panicked(&path, payload)) }),
+        };
+
+        Box::pin(async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(res) => res,
+                Err(payload) => Err(panicked(&path, payload)),
+            }
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::body::Body;
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
+use futures::future::{ok, Ready};
+use futures::Future;
+use sentry::types::Uuid;
+
+use crate::components::i18n::CATALOGUE;
+use crate::config::CONFIG;
+use crate::errors::{negotiate_format, ApiError, Rendering};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Id of the request, taken from `x-request-id` or generated; in the request
+/// extensions and echoed in the response
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /**
+     * Keep the caller's id when it is a sane header value, so a request can be
+     * followed across services
+     **/
+    fn of(req: &ServiceRequest) -> RequestId {
+        let given = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 128);
+
+        match given {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId(Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+/// Renders error responses in the language and format the request asks for,
+/// and gives every request an id.
+/// Register it outside the middlewares that reject requests; they answer with
+/// `req.error_response` rather than `Err` so their errors are rendered too.
+/// An `Err` left with no request to answer, such as a panic caught by
+/// `CatchPanic`, is rendered into the error actix answers with instead.
+pub struct Localize;
+
+impl<S> Transform<S> for Localize
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+        S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocalizeMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LocalizeMiddleware { service })
+    }
+}
+
+pub struct LocalizeMiddleware<S> {
+    service: S,
+}
+
+impl<S> Service for LocalizeMiddleware<S>
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+        S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let config = CONFIG.get();
+        let request_id = RequestId::of(&req);
+        let rendering = Rendering {
+            locale: CATALOGUE.negotiate(req.headers(), &config.i18n.default_locale),
+            format: negotiate_format(req.headers(), config.error_format),
+            instance: Some(req.path().to_string()),
+            request_id: Some(request_id.0.clone()),
+        };
+        req.extensions_mut().insert(request_id);
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let request_id = rendering.request_id.as_deref().and_then(|id| HeaderValue::from_str(id).ok());
+            let res = match fut.await {
+                Ok(res) => res,
+                Err(err) => {
+                    return Err(match ApiError::from_error(&err) {
+                        Some(err) => {
+                            let mut response = err.render(&rendering);
+                            if let Some(id) = request_id {
+                                response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), id);
+                            }
+                            response.into()
+                        },
+                        None => err,
+                    })
+                },
+            };
+
+            let mut res = match res.response().error().and_then(ApiError::from_error) {
+                Some(err) => res.into_response(err.render(&rendering)),
+                None => res,
+            };
+            if let Some(id) = request_id {
+                res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), id);
+            }
+
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_service::{Service, Transform};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use crate::components::metrics;
+
+/// Counts requests and records their latency per route pattern and status.
+/// Register it last so it wraps `BeforeAction` and sees its rejections too.
+pub struct Metrics;
+
+impl<S, B> Transform<S> for Metrics
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware { service })
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        // The pattern keeps ids out of the labels, unknown paths share one label
+        let route = req.match_pattern().unwrap_or_else(|| metrics::UNMATCHED_ROUTE.to_string());
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+            metrics::HTTP_REQUESTS.with_label_values(&labels).inc();
+            metrics::HTTP_REQUEST_DURATION
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+
+            res
+        })
+    }
+}
@@ -1,4 +1,9 @@
 // pub mod simple;
 // pub mod read_request_body;
 // pub mod read_response_body;
+pub(crate) mod admin_auth_middleware;
 pub(crate) mod before_action_middleware;
+pub(crate) mod catch_panic_middleware;
+pub(crate) mod localize_middleware;
+pub(crate) mod metrics_middleware;
+pub(crate) mod report_middleware;
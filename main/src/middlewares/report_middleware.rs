@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Payload, PayloadStream};
+use actix_web::web::BytesMut;
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
+use futures::future::{ok, Ready};
+use futures::{stream, Future, StreamExt};
+use sentry::FutureExt;
+
+use crate::components::reporting;
+use crate::config::CONFIG;
+use crate::errors::ApiError;
+use crate::middlewares::localize_middleware::RequestId;
+use crate::utils::request_info_utils::{get_request_info, CapturedBody};
+
+/// Runs each request on its own Sentry hub tagged with its id, user and role,
+/// and reports its error, if any, with the redacted request attached.
+/// Register it inside `Localize`, which assigns the request id.
+pub struct Report;
+
+impl<S: 'static, B> Transform<S> for Report
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ReportMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ReportMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct ReportMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+/**
+ * Keep the first `limit` bytes of the body in the request extensions and hand
+ * the whole body on, without buffering more than that
+ **/
+async fn capture_body(req: &mut ServiceRequest, limit: usize) -> Result<(), Error> {
+    let mut payload = req.take_payload();
+    let mut captured = BytesMut::new();
+    let mut ended = false;
+    while !ended && captured.len() < limit {
+        match payload.next().await {
+            Some(chunk) => captured.extend_from_slice(&chunk?),
+            None => ended = true,
+        }
+    }
+
+    let captured = captured.freeze();
+    req.extensions_mut().insert(CapturedBody {
+        bytes: captured.slice(..captured.len().min(limit)),
+        complete: ended && captured.len() <= limit,
+    });
+
+    let head: PayloadStream = Box::pin(stream::once(async move { Ok(captured) }).chain(payload));
+    req.set_payload(Payload::from(head));
+
+    Ok(())
+}
+
+impl<S, B> Service for ReportMiddleware<S>
+    where
+        S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+        let hub = reporting::request_hub(
+            &request_id,
+            req.method().as_str(),
+            req.path(),
+            header("x-gapo-user-id"),
+            header("x-gapo-role"),
+        );
+        let mut service = self.service.clone();
+
+        let fut = async move {
+            let body_limit = CONFIG.get().error_capture.body_limit;
+            if body_limit > 0 {
+                capture_body(&mut req, body_limit).await?;
+            }
+
+            let res = service.call(req).await?;
+
+            if let Some(mut err) = res.response().error().and_then(ApiError::from_error) {
+                if err.info.is_none() {
+                    err.info = get_request_info(res.request());
+                }
+                reporting::report(&err);
+            }
+
+            Ok(res)
+        };
+
+        Box::pin(fut.bind_hub(hub))
+    }
+}
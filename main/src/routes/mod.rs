@@ -1,3 +1,3 @@
 pub(crate) mod routes;
 
-pub use routes::init_routes;
+pub use routes::{init_admin_routes, init_ops_routes, init_routes, ROUTES};
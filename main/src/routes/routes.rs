@@ -1,6 +1,170 @@
-use crate::controllers::index_controller;
+use actix_web::web::ServiceConfig;
 
-pub fn init_routes(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(index_controller::index);
-    cfg.service(index_controller::index_test);
+use crate::controllers::{admin_controller, errors_controller, health_controller, index_controller, metrics_controller};
+
+/// Which listener serves a route
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteScope {
+    /// The public listener, behind `BeforeAction`
+    Public,
+    /// Health and metrics: the admin listener when ADMIN_BIND is set, the public one otherwise
+    Ops,
+    /// The admin listener only
+    Admin,
+}
+
+impl RouteScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RouteScope::Public => "public",
+            RouteScope::Ops => "ops",
+            RouteScope::Admin => "admin",
+        }
+    }
 }
+
+/// A registered route, listed by the `routes` command
+pub struct RouteInfo {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub handler: &'static str,
+    pub scope: RouteScope,
+    /// Adds the handler to the App
+    pub register: fn(&mut ServiceConfig),
+}
+
+/// Every route, in the order `init_routes`, `init_ops_routes` and `init_admin_routes` register them
+pub const ROUTES: &[RouteInfo] = &[
+    RouteInfo {
+        method: "GET",
+        path: "/",
+        handler: "index_controller::index",
+        scope: RouteScope::Public,
+        register: |cfg| {
+            cfg.service(index_controller::index);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/test",
+        handler: "index_controller::index_test",
+        scope: RouteScope::Public,
+        register: |cfg| {
+            cfg.service(index_controller::index_test);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/errors",
+        handler: "errors_controller::catalogue",
+        scope: RouteScope::Public,
+        register: |cfg| {
+            cfg.service(errors_controller::catalogue);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/health/live",
+        handler: "health_controller::live",
+        scope: RouteScope::Ops,
+        register: |cfg| {
+            cfg.service(health_controller::live);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/health/ready",
+        handler: "health_controller::ready",
+        scope: RouteScope::Ops,
+        register: |cfg| {
+            cfg.service(health_controller::ready);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/metrics",
+        handler: "metrics_controller::scrape",
+        scope: RouteScope::Ops,
+        register: |cfg| {
+            cfg.service(metrics_controller::scrape);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/config",
+        handler: "admin_controller::config",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::config);
+        },
+    },
+    RouteInfo {
+        method: "POST",
+        path: "/iam/refresh",
+        handler: "admin_controller::iam_refresh",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::iam_refresh);
+        },
+    },
+    RouteInfo {
+        method: "POST",
+        path: "/cache/flush-prefix",
+        handler: "admin_controller::cache_flush_prefix",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::cache_flush_prefix);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/cache/{key}",
+        handler: "admin_controller::cache_get",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::cache_get);
+        },
+    },
+    RouteInfo {
+        method: "DELETE",
+        path: "/cache/{key}",
+        handler: "admin_controller::cache_del",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::cache_del);
+        },
+    },
+    RouteInfo {
+        method: "GET",
+        path: "/log-level",
+        handler: "admin_controller::log_level",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::log_level);
+        },
+    },
+    RouteInfo {
+        method: "PUT",
+        path: "/log-level",
+        handler: "admin_controller::set_log_level",
+        scope: RouteScope::Admin,
+        register: |cfg| {
+            cfg.service(admin_controller::set_log_level);
+        },
+    },
+];
+
+/**
+ * Register the routes of `scope`
+ **/
+fn register(cfg: &mut ServiceConfig, scope: RouteScope) {
+    for route in ROUTES.iter().filter(|route| route.scope == scope) {
+        (route.register)(cfg);
+    }
+}
+
+pub fn init_routes(cfg: &mut ServiceConfig) { register(cfg, RouteScope::Public); }
+
+pub fn init_ops_routes(cfg: &mut ServiceConfig) { register(cfg, RouteScope::Ops); }
+
+pub fn init_admin_routes(cfg: &mut ServiceConfig) { register(cfg, RouteScope::Admin); }
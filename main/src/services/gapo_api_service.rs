@@ -1,202 +1,216 @@
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use actix_web::web::Data;
+use async_trait::async_trait;
 use reqwest::header;
+use sentry::protocol::Map;
 use serde::de::DeserializeOwned;
+use serde_json::json;
 
-use crate::components::databases::redis_db::RedisDB;
-use crate::config;
+use crate::components::cache::CacheBackend;
+use crate::components::typed_cache::{Cache, Namespace};
+use crate::components::{metrics, reporting};
 use crate::config::CONFIG;
-use crate::constants::error_codes::ErrorCodes;
-use crate::constants::error_messages::Messages;
 use crate::entities::app_entity::*;
-use crate::errors::ApiError;
+use crate::errors::AppError;
+
+/// user-core users by id, kept for CACHE_USER_CORE_TIME
+pub const USER_CORE_CACHE: Namespace = Namespace {
+    name: "UserCore",
+    metric: "user_core",
+    ttl: |config| config.cache_user_core_time,
+};
+
+thread_local! {
+    /// One client per worker, as its pooled connections run on the runtime that opened them
+    static CLIENT: Result<reqwest::Client, String> = reqwest::Client::builder()
+        .timeout(Duration::from_millis(CONFIG.get().upstream_timeout))
+        .build()
+        .map_err(|err| err.to_string());
+}
+
+/**
+ * GET a service-to-service endpoint, recording latency and errors per `upstream`
+ **/
+async fn get_request<T: DeserializeOwned>(upstream: &'static str, url: String, key: &str) -> Result<T, AppError> {
+    let started = Instant::now();
+    let result = send_request::<T>(url.clone(), key).await;
+
+    let outcome = match &result {
+        Ok(_) => "ok",
+        Err(err) => {
+            metrics::UPSTREAM_ERRORS.with_label_values(&[upstream, error_kind(err)]).inc();
+            error_kind(err)
+        },
+    };
+    let elapsed = started.elapsed();
+    metrics::UPSTREAM_DURATION
+        .with_label_values(&[upstream, if result.is_ok() { "ok" } else { "error" }])
+        .observe(elapsed.as_secs_f64());
+
+    let mut data = Map::new();
+    data.insert("url".to_string(), json!(url));
+    data.insert("outcome".to_string(), json!(outcome));
+    data.insert("duration_ms".to_string(), json!(elapsed.as_millis() as u64));
+    reporting::breadcrumb(upstream, format!("GET {}", upstream), result.is_err(), data);
+
+    result
+}
+
+/// Label of `upstream_request_errors_total`
+fn error_kind(err: &AppError) -> &'static str {
+    match err {
+        AppError::UpstreamTimeout(_) => "timeout",
+        AppError::UpstreamUnavailable(_) => "connect",
+        AppError::UpstreamStatus { .. } => "status",
+        AppError::Deserialize(_) => "decode",
+        _ => "request",
+    }
+}
+
+async fn send_request<T: DeserializeOwned>(url: String, key: &str) -> Result<T, AppError> {
+    let api_key = header::HeaderValue::from_str(key).map_err(|err| AppError::Internal(err.to_string()))?;
 
-async fn get_request<T: DeserializeOwned>(url: String, key: &'static str) -> Result<T, ApiError> {
     let mut headers = header::HeaderMap::new();
     headers.insert("x-gapo-role", header::HeaderValue::from_static("service"));
-    headers.insert("x-gapo-api-key", header::HeaderValue::from_static(key));
+    headers.insert("x-gapo-api-key", api_key);
+
+    let client = CLIENT.with(|client| client.clone()).map_err(AppError::Internal)?;
+    let response = client.get(&url).headers(headers).send().await?;
+    if response.status() != 200 {
+        return Err(AppError::UpstreamStatus {
+            status: response.status().as_u16(),
+            cause: format!("GET {}", response.url().path()),
+        });
+    }
 
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .build()
-        .unwrap();
-    let res = client.get(&url).send().await;
-
-    match res {
-        Ok(body) => {
-            if body.status() != 200 {
-                return Err(ApiError::new(
-                    body.status().as_u16(),
-                    Messages::SYSTEM_GENERAL_ERROR.to_string(),
-                    ErrorCodes::SYSTEM_GENERAL_ERROR,
-                    Some(Messages::SYSTEM_GENERAL_ERROR.to_string()),
-                    None,
-                ));
-            }
-
-            match body.json::<T>().await {
-                Ok(value) => Ok(value),
-                Err(er) => {
-                    return Err(ApiError::new(
-                        500,
-                        er.to_string(),
-                        ErrorCodes::SYSTEM_GENERAL_ERROR,
-                        Some(er.to_string()),
-                        None,
-                    ));
-                }
-            }
-        }
-        Err(_e) => {
-            return Err(ApiError::new(
-                400,
-                Messages::SYSTEM_GENERAL_ERROR.to_string(),
-                ErrorCodes::SYSTEM_GENERAL_ERROR,
-                Some(Messages::SYSTEM_GENERAL_ERROR.to_string()),
-                None,
-            ));
-        }
+    Ok(response.json::<T>().await?)
+}
+
+/// user-core answers 400 or 404 for users that do not exist or are blocked
+fn user_not_found(err: AppError, ids: &str) -> AppError {
+    match err {
+        AppError::UpstreamStatus { status: 400 | 404, .. } => {
+            AppError::UserNotFound(ids.to_string())
+        },
+        err => err,
     }
 }
 
-pub async fn get_user(user_id: &i64, fields: &String) -> Result<UserInfo, ApiError> {
-    let config = &CONFIG;
+pub async fn get_user(user_id: &i64, fields: &String) -> Result<UserInfo, AppError> {
+    let config = CONFIG.get();
     let url = format!(
         "{}/users/{}?fields={}",
         config.user_core_api_url, user_id, fields
     );
-    match get_request::<UserCoreResult>(url, config.user_core_api_key.as_str()).await {
+
+    match get_request::<UserCoreResult>("user_core", url, config.user_core_api_key.expose()).await {
         Ok(val) => Ok(val.data),
-        Err(_er) => {
-            return Err(ApiError::new(
-                400,
-                Messages::USER_NOT_EXIST_OR_IS_BLOCKING.to_string(),
-                ErrorCodes::USER_NOT_EXIST_OR_IS_BLOCKING,
-                Some(Messages::USER_NOT_EXIST_OR_IS_BLOCKING.to_string()),
-                None,
-            ));
-        }
+        Err(err) => Err(user_not_found(err, &user_id.to_string())),
     }
 }
 
 #[allow(unused)]
 pub async fn get_user_from_cache(
-    redis: &Data<RedisDB>,
+    cache: &dyn CacheBackend,
+    user_core: &dyn UserCoreClient,
     user_id: &i64,
     fields: &String,
-) -> Result<UserInfo, ApiError> {
-    let user = redis.get::<String>(format!("UserCore:{}", user_id));
-    if user.is_ok() {
-        let user_info_str = user.unwrap();
-        if let Ok(user_info) = serde_json::from_str(user_info_str.as_ref()) {
-            return Ok(user_info);
-        }
-    } else {
-        debug!("get_user_from_cache: {:?}", user);
-    }
-
-    match get_user(user_id, fields).await {
-        Ok(user_info) => {
-            redis.set(
-                format!("UserCore:{}", user_id),
-                serde_json::to_string(&user_info).unwrap(),
-                config::CACHE_USER_CORE_TIME,
-            );
-            Ok(user_info)
-        }
-        Err(err) => Err(err),
-    }
+) -> Result<UserInfo, AppError> {
+    Cache::new(cache, &USER_CORE_CACHE)
+        .get_or_load(user_id, || user_core.get_user(*user_id, fields))
+        .await
 }
 
 #[allow(unused)]
-pub async fn get_users(ids: Vec<String>, fields: &String) -> Result<Vec<UserInfo>, ApiError> {
-    let config = &CONFIG;
+pub async fn get_users(ids: Vec<String>, fields: &String) -> Result<Vec<UserInfo>, AppError> {
+    let config = CONFIG.get();
     let url = format!(
         "{}/users?ids={}&fields={}",
         config.user_core_api_url,
         ids.join(","),
         fields
     );
-    match get_request::<UsersCoreResult>(url, config.user_core_api_key.as_str()).await {
+
+    match get_request::<UsersCoreResult>("user_core", url, config.user_core_api_key.expose()).await {
         Ok(val) => Ok(val.data),
-        Err(_er) => {
-            return Err(ApiError::new(
-                400,
-                Messages::USER_NOT_EXIST_OR_IS_BLOCKING.to_string(),
-                ErrorCodes::USER_NOT_EXIST_OR_IS_BLOCKING,
-                Some(Messages::USER_NOT_EXIST_OR_IS_BLOCKING.to_string()),
-                None,
-            ));
-        }
+        Err(err) => Err(user_not_found(err, &ids.join(","))),
     }
 }
 
 #[allow(unused)]
 pub async fn get_users_from_cache(
-    redis: &Data<RedisDB>,
+    cache: &dyn CacheBackend,
+    user_core: &dyn UserCoreClient,
     ids: Vec<String>,
     fields: &String,
-) -> Result<HashMap<String, UserInfo>, ApiError> {
-    let mut users_map: HashMap<String, UserInfo> = HashMap::new();
-    let mut ids_vec_not_cache: Vec<String> = vec![];
-
-    let keys: Vec<String> = ids
-        .clone()
-        .into_iter()
-        .map(|item| format!("UserCore:{}", item.trim()))
-        .collect();
-    let users_cache = redis.mget(keys.clone());
-
-    if users_cache.is_ok() {
-        let mut id: usize = 0;
-        for item in users_cache.unwrap() {
-            let ids_vec_clone = ids.clone();
-            if item == "nil" {
-                ids_vec_not_cache.push(ids_vec_clone[id].trim().to_string());
-            } else {
-                if let Ok(user_info) = serde_json::from_str(item.as_ref()) {
-                    users_map.insert(ids_vec_clone[id].to_string(), user_info);
-                }
-            }
-
-            id += 1;
-        }
-
-        if ids_vec_not_cache.len() > 0 {
-            let users_info = get_users(ids_vec_not_cache, &USER_INFO_FIELDS.to_string()).await;
-            if users_info.is_ok() {
-                for user_info in users_info.unwrap() {
-                    redis.set(
-                        format!("UserCore:{}", user_info.id),
-                        serde_json::to_string(&user_info).unwrap(),
-                        config::CACHE_USER_CORE_TIME,
-                    );
-
-                    users_map.insert(user_info.id.to_string(), user_info);
-                }
-            }
-        }
+) -> Result<HashMap<String, UserInfo>, AppError> {
+    let ids: Vec<String> = ids.iter().map(|id| id.trim().to_string()).collect();
+
+    Cache::new(cache, &USER_CORE_CACHE)
+        .get_many_or_load(&ids, |missed| async move {
+            let users = user_core.get_users(missed, USER_INFO_FIELDS).await?;
+
+            Ok(users.into_iter().map(|user| (user.id.to_string(), user)).collect())
+        })
+        .await
+}
+
+/// user-core API, called over HTTP in production
+#[async_trait(?Send)]
+pub trait UserCoreClient: Send + Sync {
+    async fn get_user(&self, user_id: i64, fields: &str) -> Result<UserInfo, AppError>;
+
+    async fn get_users(&self, ids: Vec<String>, fields: &str) -> Result<Vec<UserInfo>, AppError>;
+}
+
+/// Calls USER_CORE_API_URL
+pub struct HttpUserCoreClient;
+
+#[async_trait(?Send)]
+impl UserCoreClient for HttpUserCoreClient {
+    async fn get_user(&self, user_id: i64, fields: &str) -> Result<UserInfo, AppError> {
+        get_user(&user_id, &fields.to_string()).await
     }
 
-    Ok(users_map)
+    async fn get_users(&self, ids: Vec<String>, fields: &str) -> Result<Vec<UserInfo>, AppError> {
+        get_users(ids, &fields.to_string()).await
+    }
 }
 
 #[allow(unused)]
-pub async fn get_iam_keys() -> Result<Vec<IamKey>, ApiError> {
-    let config = &CONFIG;
+pub async fn get_iam_keys() -> Result<Vec<IamKey>, AppError> {
+    let config = CONFIG.get();
     let url = config.iam_api.clone();
 
-    match get_request::<IamKeysResult>(url, config.iam_key.as_str()).await {
-        Ok(h) => Ok(h.data),
-        Err(err) => {
-            return Err(ApiError::new(
-                400,
-                err.to_string(),
-                ErrorCodes::UNKNOWN,
-                Some(err.to_string()),
-                None,
-            ));
-        }
+    Ok(get_request::<IamKeysResult>("iam", url, config.iam_key.expose()).await?.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::cache::MemoryCache;
+    use crate::test::fakes::FakeUserCore;
+
+    #[actix_rt::test]
+    async fn users_are_served_from_the_cache_once_fetched() {
+        let cache = MemoryCache::new();
+        let user_core = FakeUserCore::with_users(&[1, 2]);
+        let fields = USER_INFO_FIELDS.to_string();
+
+        let user = get_user_from_cache(&cache, &user_core, &1, &fields).await.unwrap();
+        assert_eq!(user.id, 1);
+        assert_eq!(user_core.requested(), 1);
+
+        let ids = vec!["1".to_string(), "2".to_string(), "3".to_string()];
+        let users = get_users_from_cache(&cache, &user_core, ids.clone(), &fields).await.unwrap();
+        assert_eq!(users.len(), 2);
+        // Only the uncached 2 and 3 went to user-core
+        assert_eq!(user_core.requested(), 3);
+
+        let users = get_users_from_cache(&cache, &user_core, ids, &fields).await.unwrap();
+        assert_eq!(users.len(), 2);
+        // Unknown users are never cached, so 3 is asked for again
+        assert_eq!(user_core.requested(), 4);
     }
 }
@@ -0,0 +1,197 @@
+//! Dependency checks behind `/health/ready`
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_rt::time::timeout;
+use actix_web::web::Data;
+use serde::Serialize;
+
+use crate::components::cache::CacheBackend;
+use crate::config::{HealthConfig, CONFIG};
+use crate::services::iam_service::{IamKeyStore, IamKeys};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Fail,
+}
+
+/// Outcome of one dependency check
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub status: Status,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Every check, the instance is ready when all of them pass
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: Status,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Readiness {
+    pub fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let ready = checks.values().all(|check| check.status == Status::Ok);
+
+        Readiness {
+            status: if ready { Status::Ok } else { Status::Fail },
+            checks,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool { self.status == Status::Ok }
+}
+
+/**
+ * Run `probe` with a deadline, timing it
+ **/
+async fn timed<F: Future<Output = Result<Option<String>, String>>>(limit: Duration, probe: F) -> Check {
+    let started = Instant::now();
+    let result = match timeout(limit, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}ms", limit.as_millis())),
+    };
+    let latency_ms = (started.elapsed().as_secs_f64() * 100_000.0).round() / 100.0;
+
+    match result {
+        Ok(detail) => Check {
+            status: Status::Ok,
+            latency_ms,
+            detail,
+        },
+        Err(detail) => Check {
+            status: Status::Fail,
+            latency_ms,
+            detail: Some(detail),
+        },
+    }
+}
+
+/**
+ * PING the cache
+ **/
+pub async fn check_cache(cache: Data<dyn CacheBackend>, limit: Duration) -> Check {
+    timed(limit, async move { cache.ping(limit).await.map(|_| None).map_err(|err| err.to_string()) }).await
+}
+
+/**
+ * The IAM keys must be loaded and refreshed within `max_age`
+ **/
+pub async fn check_iam_keys(store: &Mutex<IamKeyStore>, max_age: Duration) -> Check {
+    timed(max_age, async {
+        let store = store.lock().map_err(|_| "the key store lock is poisoned".to_string())?;
+
+        match store.age() {
+            _ if store.is_empty() => Err("no keys loaded".to_string()),
+            Some(age) if age > max_age => Err(format!(
+                "{} keys loaded {}s ago, older than {}s",
+                store.len(),
+                age.as_secs(),
+                max_age.as_secs()
+            )),
+            age => Ok(Some(format!(
+                "{} keys loaded {}s ago",
+                store.len(),
+                age.unwrap_or_default().as_secs()
+            ))),
+        }
+    })
+    .await
+}
+
+/**
+ * user-core answers HTTP at all, whatever the status code
+ **/
+pub async fn check_user_core(limit: Duration) -> Check {
+    let url = CONFIG.get().user_core_api_url.clone();
+
+    timed(limit, async move {
+        let client = reqwest::Client::builder()
+            .timeout(limit)
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        match client.get(&url).send().await {
+            Ok(response) => Ok(Some(format!("HTTP {}", response.status().as_u16()))),
+            Err(err) => Err(err.to_string()),
+        }
+    })
+    .await
+}
+
+/**
+ * Run every configured check concurrently
+ **/
+pub async fn readiness(cache: Data<dyn CacheBackend>, iam_keys: &IamKeys, health: &HealthConfig) -> Readiness {
+    let limit = Duration::from_millis(health.timeout);
+    let max_age = Duration::from_secs(health.iam_max_age);
+
+    let user_core = async {
+        if health.check_user_core {
+            Some(check_user_core(limit).await)
+        } else {
+            None
+        }
+    };
+    let (cache, iam_keys, user_core) =
+        futures::join!(check_cache(cache, limit), check_iam_keys(iam_keys.store(), max_age), user_core);
+    let mut checks = BTreeMap::new();
+    checks.insert("cache", cache);
+    checks.insert("iam_keys", iam_keys);
+    if let Some(user_core) = user_core {
+        checks.insert("user_core", user_core);
+    }
+
+    Readiness::new(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn store(keys: usize, loaded_secs_ago: u64) -> Mutex<IamKeyStore> {
+        let keys: HashMap<String, String> = (0..keys).map(|i| (format!("key-{}", i), "source".to_string())).collect();
+        let loaded_at = Instant::now() - Duration::from_secs(loaded_secs_ago);
+
+        Mutex::new(IamKeyStore::new(keys).loaded_at(loaded_at))
+    }
+
+    #[actix_rt::test]
+    async fn iam_keys_must_be_loaded_and_fresh() {
+        let max_age = Duration::from_secs(60);
+
+        let fresh = check_iam_keys(&store(2, 5), max_age).await;
+        assert_eq!(fresh.status, Status::Ok);
+        assert_eq!(fresh.detail.as_deref(), Some("2 keys loaded 5s ago"));
+
+        let stale = check_iam_keys(&store(2, 120), max_age).await;
+        assert_eq!(stale.status, Status::Fail);
+
+        let empty = check_iam_keys(&Mutex::new(IamKeyStore::default()), max_age).await;
+        assert_eq!(empty.detail.as_deref(), Some("no keys loaded"));
+    }
+
+    #[actix_rt::test]
+    async fn one_failing_check_fails_readiness() {
+        let mut checks = BTreeMap::new();
+        checks.insert("iam_keys", check_iam_keys(&store(1, 0), Duration::from_secs(60)).await);
+        assert!(Readiness::new(checks.clone()).is_ready());
+
+        checks.insert("cache", timed(Duration::from_millis(10), futures::future::pending()).await);
+        let readiness = Readiness::new(checks);
+        let json = serde_json::to_value(&readiness).unwrap();
+
+        assert!(!readiness.is_ready());
+        assert_eq!(json["status"], "fail");
+        assert_eq!(json["checks"]["cache"]["detail"], "timed out after 10ms");
+        assert_eq!(json["checks"]["iam_keys"]["status"], "ok");
+    }
+}
@@ -1,18 +1,172 @@
 use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_rt::time::interval;
+use actix_web::web::Data;
+use async_trait::async_trait;
 
 use super::gapo_api_service::get_iam_keys;
+use crate::config::CONFIG;
+use crate::errors::AppError;
+
+/// Service api keys (api key => source) loaded from IAM
+#[derive(Debug, Default)]
+pub struct IamKeyStore {
+    keys: HashMap<String, String>,
+    loaded_at: Option<Instant>,
+}
+
+impl IamKeyStore {
+    #[allow(unused)]
+    pub fn new(keys: HashMap<String, String>) -> Self {
+        let mut store = IamKeyStore::default();
+        store.replace(keys);
+
+        store
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> { self.keys.get(key) }
+
+    pub fn len(&self) -> usize { self.keys.len() }
+
+    pub fn is_empty(&self) -> bool { self.keys.is_empty() }
+
+    /// Time since the keys were last loaded successfully
+    pub fn age(&self) -> Option<Duration> { self.loaded_at.map(|loaded_at| loaded_at.elapsed()) }
+
+    /// Swap in freshly loaded keys, an empty result keeps the current ones
+    pub fn replace(&mut self, keys: HashMap<String, String>) {
+        if keys.is_empty() {
+            return;
+        }
+
+        self.keys = keys;
+        self.loaded_at = Some(Instant::now());
+    }
+
+    #[cfg(test)]
+    pub fn loaded_at(mut self, loaded_at: Instant) -> Self {
+        self.loaded_at = Some(loaded_at);
+        self
+    }
+}
+
+/// Where the service keys come from, IAM_API in production
+#[async_trait(?Send)]
+pub trait IamClient: Send + Sync {
+    /// Every service key, api key => source
+    async fn service_keys(&self) -> Result<HashMap<String, String>, AppError>;
+}
+
+/// Reads the keys from IAM_API
+pub struct HttpIamClient;
+
+#[async_trait(?Send)]
+impl IamClient for HttpIamClient {
+    async fn service_keys(&self) -> Result<HashMap<String, String>, AppError> {
+        let iam_keys = get_iam_keys().await?;
+
+        Ok(iam_keys.into_iter().map(|iam_key| (iam_key.apiKey, iam_key.source)).collect())
+    }
+}
+
+/// The key store with the client that fills it, shared as `Data<IamKeys>`
+pub struct IamKeys {
+    store: Mutex<IamKeyStore>,
+    client: Box<dyn IamClient>,
+}
+
+impl IamKeys {
+    pub fn new<C: IamClient + 'static>(client: C) -> Self {
+        IamKeys {
+            store: Mutex::new(IamKeyStore::default()),
+            client: Box::new(client),
+        }
+    }
+
+    pub fn store(&self) -> &Mutex<IamKeyStore> { &self.store }
+
+    /**
+     * Fetch the keys from the client, returning how many were loaded
+     **/
+    pub async fn load(&self) -> Result<usize, AppError> {
+        let keys = self.client.service_keys().await?;
+        let count = keys.len();
+        if let Ok(mut store) = self.store.lock() {
+            store.replace(keys);
+        }
+
+        Ok(count)
+    }
+
+    /**
+     * Whether `key` is a known service key, reloading once when it is not
+     * so keys created after the last load are accepted
+     **/
+    pub async fn verify(&self, key: &str) -> bool {
+        let known = |store: &Mutex<IamKeyStore>| store.lock().map(|keys| keys.get(key).is_some()).unwrap_or(false);
+        if known(&self.store) {
+            return true;
+        }
+
+        if let Err(err) = self.load().await {
+            warn!("Could not reload the IAM keys: {}", err);
+        }
+        known(&self.store)
+    }
+}
+
+/**
+ * Reload the keys every IAM_REFRESH_INTERVAL seconds so revoked keys go away
+ * and readiness can tell whether IAM is still reachable
+ **/
+pub async fn refresh_iam_keys(iam_keys: Data<IamKeys>) {
+    let every = CONFIG.get().iam_refresh_interval;
+    if every == 0 {
+        return;
+    }
+
+    let mut ticker = interval(Duration::from_secs(every));
+    // The first tick completes immediately and the keys were just loaded
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        if let Err(err) = iam_keys.load().await {
+            warn!("Could not refresh the IAM keys, keeping the current ones: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fakes::StaticIam;
+
+    #[test]
+    fn keeps_keys_when_a_reload_comes_back_empty() {
+        let mut keys = HashMap::new();
+        keys.insert("key".to_string(), "post-service".to_string());
+
+        let mut store = IamKeyStore::new(HashMap::new());
+        assert!(store.is_empty());
+        assert_eq!(store.age(), None);
+
+        store.replace(keys);
+        store.replace(HashMap::new());
 
-#[allow(unused)]
-pub async fn get_iam_keys_for_init() -> HashMap<String, String> {
-    let iam_keys = match get_iam_keys().await {
-        Ok(h) => h,
-        Err(_er) => vec![],
-    };
+        assert_eq!(store.get("key"), Some(&"post-service".to_string()));
+        assert!(store.age().is_some());
+    }
 
-    let mut hash: HashMap<String, String> = HashMap::new();
-    iam_keys.iter().for_each(|iam_key| {
-        hash.insert(iam_key.apiKey.clone(), iam_key.source.clone());
-    });
+    #[actix_rt::test]
+    async fn reloads_once_for_unknown_keys() {
+        let iam_keys = IamKeys::new(StaticIam::new(&[("key", "post-service")]));
 
-    hash
+        assert!(iam_keys.verify("key").await);
+        assert!(!iam_keys.verify("other").await);
+        assert_eq!(iam_keys.store().lock().unwrap().len(), 1);
+    }
 }
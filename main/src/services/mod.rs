@@ -1,2 +1,3 @@
 pub(crate) mod gapo_api_service;
+pub(crate) mod health_service;
 pub(crate) mod iam_service;
@@ -0,0 +1,88 @@
+//! In-memory stand-ins for the services the App talks to
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+
+use crate::entities::app_entity::UserInfo;
+use crate::errors::AppError;
+use crate::services::gapo_api_service::UserCoreClient;
+use crate::services::iam_service::IamClient;
+
+/// IAM that always returns the same keys
+pub struct StaticIam(HashMap<String, String>);
+
+impl StaticIam {
+    pub fn new(keys: &[(&str, &str)]) -> Self {
+        StaticIam(keys.iter().map(|(key, source)| (key.to_string(), source.to_string())).collect())
+    }
+}
+
+#[async_trait(?Send)]
+impl IamClient for StaticIam {
+    async fn service_keys(&self) -> Result<HashMap<String, String>, AppError> { Ok(self.0.clone()) }
+}
+
+/// IAM that accepts the connection and never answers
+pub struct SilentIam;
+
+#[async_trait(?Send)]
+impl IamClient for SilentIam {
+    async fn service_keys(&self) -> Result<HashMap<String, String>, AppError> { futures::future::pending().await }
+}
+
+/// user-core knowing a fixed set of users, counting the users it was asked for
+#[derive(Default)]
+pub struct FakeUserCore {
+    users: HashMap<i64, UserInfo>,
+    pub requested: AtomicUsize,
+}
+
+impl FakeUserCore {
+    pub fn with_users(ids: &[i64]) -> Self {
+        FakeUserCore {
+            users: ids.iter().map(|id| (*id, user(*id))).collect(),
+            requested: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn requested(&self) -> usize { self.requested.load(Ordering::SeqCst) }
+}
+
+#[async_trait(?Send)]
+impl UserCoreClient for FakeUserCore {
+    async fn get_user(&self, user_id: i64, _fields: &str) -> Result<UserInfo, AppError> {
+        self.requested.fetch_add(1, Ordering::SeqCst);
+
+        self.users
+            .get(&user_id)
+            .cloned()
+            .ok_or_else(|| AppError::UserNotFound(user_id.to_string()))
+    }
+
+    async fn get_users(&self, ids: Vec<String>, _fields: &str) -> Result<Vec<UserInfo>, AppError> {
+        self.requested.fetch_add(ids.len(), Ordering::SeqCst);
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| id.parse::<i64>().ok())
+            .filter_map(|id| self.users.get(&id).cloned())
+            .collect())
+    }
+}
+
+pub fn user(id: i64) -> UserInfo {
+    UserInfo {
+        id,
+        full_name: format!("User {}", id),
+        display_name: format!("user{}", id),
+        cover: String::new(),
+        avatar: String::new(),
+        link_profile: String::new(),
+        status: 1,
+        status_verify: 0,
+        avatar_thumb_pattern: String::new(),
+        cover_thumb_pattern: String::new(),
+    }
+}
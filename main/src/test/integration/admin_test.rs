@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod test {
+    use crate::app::AppBuilder;
+    use crate::config::{Config, CONFIG};
+    use crate::constants::error_codes::ErrorCodes;
+    use crate::services::iam_service::IamKeys;
+    use crate::test::fakes::StaticIam;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Data;
+    use std::sync::Arc;
+
+    fn builder() -> AppBuilder {
+        let mut config: Config = (*CONFIG.get()).clone();
+        config.admin.bind = Some("127.0.0.1:9000".to_string());
+
+        AppBuilder::new(Arc::new(config)).iam_keys(Data::new(IamKeys::new(StaticIam::new(&[("post-key", "post")]))))
+    }
+
+    #[actix_rt::test]
+    async fn ops_routes_move_to_the_admin_listener() {
+        let mut public = test::init_service(builder().build()).await;
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+
+        assert_eq!(test::call_service(&mut public, req).await.status(), StatusCode::NOT_FOUND);
+
+        let mut admin = test::init_service(builder().build_admin()).await;
+        for uri in &["/metrics", "/health/live", "/log-level"] {
+            let req = test::TestRequest::get().uri(uri).peer_addr("127.0.0.1:40000".parse().unwrap()).to_request();
+
+            assert_eq!(test::call_service(&mut admin, req).await.status(), StatusCode::OK, "{}", uri);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn admin_routes_work_on_the_injected_dependencies() {
+        let mut admin = test::init_service(builder().build_admin()).await;
+        let local = "127.0.0.1:40000".parse().unwrap();
+
+        let req = test::TestRequest::post().uri("/iam/refresh").peer_addr(local).to_request();
+        let body: serde_json::Value = test::read_response_json(&mut admin, req).await;
+        assert_eq!(body["keys"], 1);
+
+        let req = test::TestRequest::get().uri("/config").peer_addr(local).to_request();
+        let body: serde_json::Value = test::read_response_json(&mut admin, req).await;
+        assert_eq!(body["values"]["redis_uri"], "***");
+
+        let req = test::TestRequest::post()
+            .uri("/cache/flush-prefix?prefix=UserCore:&dry_run=true")
+            .peer_addr(local)
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut admin, req).await;
+        assert_eq!(body["keys"], serde_json::json!([]));
+
+        let req = test::TestRequest::get().uri("/cache/UserCore:1").peer_addr(local).to_request();
+        assert_eq!(test::call_service(&mut admin, req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn bodies_longer_than_the_capture_limit_reach_the_handler() {
+        let mut admin = test::init_service(builder().build_admin()).await;
+        let local = "127.0.0.1:40000".parse().unwrap();
+
+        let req = test::TestRequest::get().uri("/log-level").peer_addr(local).to_request();
+        let current: serde_json::Value = test::read_response_json(&mut admin, req).await;
+
+        // Set the filter it already has, the body is what matters
+        let padding = "x".repeat(CONFIG.get().error_capture.body_limit * 2);
+        let payload = serde_json::json!({ "filter": current["filter"], "padding": padding });
+        let req = test::TestRequest::put().uri("/log-level").peer_addr(local).set_json(&payload).to_request();
+        let body: serde_json::Value = test::read_response_json(&mut admin, req).await;
+        assert_eq!(body, current);
+    }
+
+    #[actix_rt::test]
+    async fn unreadable_requests_name_the_fields_at_fault() {
+        let mut admin = test::init_service(builder().build_admin()).await;
+        let local = "127.0.0.1:40000".parse().unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/cache/flush-prefix?dry_run=true")
+            .peer_addr(local)
+            .header("accept-language", "en")
+            .to_request();
+        let res = test::call_service(&mut admin, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], ErrorCodes::INVALID_REQUEST);
+        assert_eq!(
+            body["violations"],
+            serde_json::json!([{ "field": "prefix", "code": "required", "message": "prefix is required." }])
+        );
+
+        let req = test::TestRequest::put()
+            .uri("/log-level")
+            .peer_addr(local)
+            .header("content-type", "application/json")
+            .set_payload("{\"filter\": ")
+            .to_request();
+        let res = test::call_service(&mut admin, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["violations"][0]["field"], "body");
+        assert_eq!(body["violations"][0]["code"], "malformed");
+    }
+}
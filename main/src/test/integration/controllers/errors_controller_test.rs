@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod test {
+    use crate::app::AppBuilder;
+    use crate::components::cache::MemoryCache;
+    use crate::config::CONFIG;
+    use crate::constants::error_codes::{ErrorCodes, ERROR_CODES};
+    use crate::services::iam_service::IamKeys;
+    use crate::test::fakes::{FakeUserCore, StaticIam};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Data;
+
+    #[actix_rt::test]
+    async fn serves_the_registry_in_the_requested_language() {
+        let app = AppBuilder::new(CONFIG.get())
+            .cache(MemoryCache::new())
+            .iam_keys(Data::new(IamKeys::new(StaticIam::new(&[]))))
+            .user_core(FakeUserCore::default())
+            .build();
+        let mut app = test::init_service(app).await;
+
+        let req = test::TestRequest::get()
+            .uri("/errors")
+            .header("x-gapo-role", "user")
+            .header("x-gapo-user-id", "10")
+            .header("accept-language", "en")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(body["locale"], "en");
+        assert_eq!(errors.len(), ERROR_CODES.len());
+
+        let role = errors.iter().find(|error| error["code"] == ErrorCodes::GAPO_ROLE_NOT_SUPPORT).unwrap();
+        assert_eq!(role["name"], "GAPO_ROLE_NOT_SUPPORT");
+        assert_eq!(role["message"], "Your role is not supported.");
+
+        let required = body["violations"].as_array().unwrap().iter().find(|v| v["code"] == "required").unwrap();
+        assert_eq!(required["message"], "{field} is required.");
+    }
+}
@@ -1,25 +1,28 @@
 #[cfg(test)]
 mod test {
-    use crate::app::Application;
+    use crate::app::AppBuilder;
+    use crate::components::cache::MemoryCache;
+    use crate::components::i18n::CATALOGUE;
     use crate::config::CONFIG;
-    use actix_web::{test, App};
-    use futures_util::TryStreamExt;
-    use std::str::from_utf8;
-    use crate::components::databases::redis_db::RedisDB;
+    use crate::constants::error_codes::ErrorCodes;
+    use crate::services::iam_service::IamKeys;
+    use crate::test::fakes::{FakeUserCore, StaticIam};
+    use actix_service::Service;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::{HeaderValue, StatusCode};
+    use actix_web::{test, Error};
     use actix_web::web::Data;
-    use crate::controllers::index_controller;
+
+    fn builder() -> AppBuilder {
+        AppBuilder::new(CONFIG.get())
+            .cache(MemoryCache::new())
+            .iam_keys(Data::new(IamKeys::new(StaticIam::new(&[("post-key", "post-service")]))))
+            .user_core(FakeUserCore::default())
+    }
 
     #[actix_rt::test]
     async fn test_index_get() {
-        let config = &CONFIG;
-        println!("->>>>>>>>>>>>>>>>>>>>>>>....{:?}", config.env);
-        let mut app = test::init_service(App::new().configure(Application::config_app())).await;
-
-        // let redis = app.data().expect("get iam key from app_data failse");
-        // let iam_keys_data = req
-        //     .app_data::<Data<Mutex<HashMap<String, String>>>>()
-            // .expect("get iam key from app_data failse");
-        let redis = RedisDB::connect(config.redis_uri.clone());
+        let mut app = test::init_service(builder().build()).await;
 
         let mut test_request = test::TestRequest::get().uri("/");
         test_request = test_request.header("content-type", "text/plain");
@@ -27,18 +30,148 @@ mod test {
         test_request = test_request.header("x-gapo-user-id", "10");
 
         let req = test_request.to_request();
-        let mut response = test::call_service(&mut app, req).await;
-
-        let bytes = response
-            .take_body()
-            .try_fold(Vec::new(), |mut acc, chunk| async {
-                acc.extend(chunk);
-                Ok(acc)
-            });
-        let a = bytes.await.unwrap();
-        let b = from_utf8(a.as_slice()).unwrap();
-
-        assert_eq!(b, "Hello world");
-        assert!(response.status().is_success());
+        let body = test::read_response(&mut app, req).await;
+
+        assert_eq!(body, "Hello world");
+    }
+
+    #[actix_rt::test]
+    async fn service_requests_need_a_known_api_key() {
+        let mut app = test::init_service(builder().build()).await;
+
+        for (key, status) in &[("post-key", StatusCode::OK), ("unknown", StatusCode::FORBIDDEN)] {
+            let req = test::TestRequest::get()
+                .uri("/")
+                .header("x-gapo-role", "service")
+                .header("x-gapo-api-key", *key)
+                .to_request();
+            let response = test::call_service(&mut app, req).await;
+
+            assert_eq!(response.status(), *status, "api key {}", key);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn rejected_credentials_keep_the_invalid_request_code() {
+        let mut app = test::init_service(builder().build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header("x-gapo-role", "service")
+            .header("x-gapo-api-key", "unknown")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["http_code"], 403);
+        assert_eq!(body["code"], ErrorCodes::INVALID_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn rejections_speak_the_requested_language() {
+        let mut app = test::init_service(builder().build()).await;
+
+        for (header, value, locale) in &[
+            ("accept-language", "en-US,en;q=0.9", "en"),
+            ("accept-language", "fr", "vi"),
+            ("x-gapo-lang", "vi", "vi"),
+        ] {
+            let req = test::TestRequest::get()
+                .uri("/")
+                .header("x-gapo-role", "service")
+                .header("x-gapo-api-key", "unknown")
+                .header("accept-language", "en")
+                .header(*header, *value)
+                .to_request();
+            let response = test::call_service(&mut app, req).await;
+            let language = response.headers().get("content-language").unwrap().to_str().unwrap().to_string();
+            let body: serde_json::Value = test::read_body_json(response).await;
+
+            assert_eq!(language, *locale, "{}: {}", header, value);
+            assert_eq!(
+                body["message"],
+                CATALOGUE.message(locale, ErrorCodes::INVALID_REQUEST, &Default::default()).unwrap(),
+                "{}: {}",
+                header,
+                value
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn rejections_as_problem_json_on_request() {
+        let mut app = test::init_service(builder().build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header("x-gapo-role", "service")
+            .header("x-gapo-api-key", "unknown")
+            .header("accept", "application/problem+json")
+            .header("x-request-id", "req-42")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/problem+json");
+        assert_eq!(response.headers().get("x-request-id").unwrap(), "req-42");
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["type"], format!("urn:gapo:error:{}", ErrorCodes::INVALID_REQUEST));
+        assert_eq!(body["status"], 403);
+        assert_eq!(body["code"], ErrorCodes::INVALID_REQUEST);
+        assert_eq!(body["instance"], "/");
+        assert_eq!(body["request_id"], "req-42");
+        assert!(body.get("http_code").is_none());
+    }
+
+    /**
+     * The response actix answers with for a request that failed with `Err`
+     **/
+    fn failed(result: Result<ServiceResponse, Error>) -> ServiceResponse {
+        match result {
+            Ok(response) => panic!("answered {} instead of failing", response.status()),
+            Err(err) => ServiceResponse::new(test::TestRequest::default().to_http_request(), err.into()),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn a_panicking_request_gets_a_500_and_the_worker_keeps_serving() {
+        let mut app = test::init_service(builder().build()).await;
+
+        // Not valid text, `BeforeAction` unwraps `to_str()` on it
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header("x-gapo-role", HeaderValue::from_bytes(&[0xff]).unwrap())
+            .to_request();
+        let response = failed(app.call(req).await);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], ErrorCodes::SYSTEM_GENERAL_ERROR);
+
+        let req = test::TestRequest::get().uri("/").header("x-gapo-role", "user").to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn a_panic_is_rendered_as_the_request_asks() {
+        let mut app = test::init_service(builder().build()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header("x-gapo-role", HeaderValue::from_bytes(&[0xff]).unwrap())
+            .header("accept", "application/problem+json")
+            .header("x-request-id", "req-43")
+            .to_request();
+        let response = failed(app.call(req).await);
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers().get("content-type").unwrap(), "application/problem+json");
+        assert_eq!(response.headers().get("x-request-id").unwrap(), "req-43");
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], 500);
+        assert_eq!(body["code"], ErrorCodes::SYSTEM_GENERAL_ERROR);
+        assert_eq!(body["request_id"], "req-43");
     }
 }
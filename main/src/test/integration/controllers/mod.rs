@@ -1 +1,2 @@
+pub mod errors_controller_test;
 pub mod index_controllers_test;
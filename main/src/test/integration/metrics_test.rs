@@ -0,0 +1,24 @@
+#[cfg(test)]
+mod test {
+    use crate::middlewares::metrics_middleware::Metrics;
+    use crate::routes::init_ops_routes;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn records_requests_per_route_pattern() {
+        let mut app = test::init_service(App::new().configure(init_ops_routes).wrap(Metrics)).await;
+
+        for uri in &["/health/live", "/users/42/not-a-route"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            test::call_service(&mut app, req).await;
+        }
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = String::from_utf8(test::read_response(&mut app, req).await.to_vec()).unwrap();
+
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/health/live",status="200"}"#));
+        assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"}"#));
+        assert!(body.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/health/live",status="200""#));
+        assert!(!body.contains("/users/42"));
+    }
+}
@@ -1 +1,8 @@
+pub mod admin_test;
 pub mod controllers;
+pub mod metrics_test;
+pub mod redis_test;
+pub mod routes_test;
+pub mod startup_test;
+#[cfg(feature = "openssl")]
+pub mod tls_test;
@@ -0,0 +1,225 @@
+#[cfg(test)]
+mod test {
+    //! Against redis-server processes started for each test, so they need
+    //! `redis-server` on PATH: `cargo test -p main redis_test -- --ignored`
+
+    use std::fs;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    use actix_rt::time::delay_for;
+
+    use crate::components::databases::redis_db::RedisDB;
+    use crate::components::databases::redis_topology::{key_slot, SLOTS};
+    use crate::config::{RedisConfig, RedisMode};
+
+    /// A redis-server in a directory of its own, killed on drop
+    struct Server {
+        port: u16,
+        dir: PathBuf,
+        child: Child,
+    }
+
+    impl Server {
+        fn start(conf: &str) -> Server { Server::spawn(conf, false) }
+
+        fn sentinel(conf: &str) -> Server { Server::spawn(conf, true) }
+
+        fn spawn(conf: &str, sentinel: bool) -> Server {
+            let port = free_port();
+            let dir = std::env::temp_dir().join(format!("redis_test_{}_{}", std::process::id(), port));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("redis.conf");
+            fs::write(&path, format!("port {}\nbind 127.0.0.1\ndir {}\n{}\n", port, dir.display(), conf)).unwrap();
+
+            let mut command = Command::new("redis-server");
+            command.arg(&path).stdout(Stdio::null());
+            if sentinel {
+                command.arg("--sentinel");
+            }
+            let child = command.spawn().expect("redis-server is not on PATH");
+
+            let server = Server { port, dir, child };
+            wait_for("redis-server to start", || {
+                server.conn().ok().and_then(|mut conn| redis::cmd("PING").query::<String>(&mut conn).ok()).is_some()
+            });
+
+            server
+        }
+
+        fn addr(&self) -> String { format!("127.0.0.1:{}", self.port) }
+
+        fn conn(&self) -> redis::RedisResult<redis::Connection> {
+            redis::Client::open(format!("redis://{}/", self.addr()))?.get_connection()
+        }
+
+        fn query<T: redis::FromRedisValue>(&self, cmd: &mut redis::Cmd) -> T {
+            cmd.query(&mut self.conn().unwrap()).unwrap()
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            self.child.kill().ok();
+            self.child.wait().ok();
+            fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    /// A port free along with the cluster bus port 10000 above it
+    fn free_port() -> u16 {
+        loop {
+            let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+            if port < 55000 && TcpListener::bind(("127.0.0.1", port + 10000)).is_ok() {
+                return port;
+            }
+        }
+    }
+
+    fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn config(mode: RedisMode, sentinels: Vec<String>, cluster_nodes: Vec<String>) -> RedisConfig {
+        RedisConfig {
+            mode,
+            sentinel_master: Some("mymaster".to_string()),
+            sentinels,
+            cluster_nodes,
+        }
+    }
+
+    fn keys(names: &[&str]) -> Vec<String> { names.iter().map(|name| name.to_string()).collect() }
+
+    #[actix_rt::test]
+    #[ignore = "needs redis-server on PATH"]
+    async fn standalone_runs_every_command_on_one_server() {
+        let server = Server::start("");
+        let config = config(RedisMode::Standalone, vec![], vec![]);
+        let redis = RedisDB::connect(format!("redis://{}/3", server.addr()), &config).unwrap();
+
+        assert!(redis.set("a".to_string(), "1".to_string(), 60).await);
+        assert!(redis.set("b".to_string(), "2".to_string(), 0).await);
+        let values = redis.mget(keys(&["a", "missing", "b"])).await.unwrap();
+        assert_eq!(values, vec![Some("1".to_string()), None, Some("2".to_string())]);
+        assert_eq!(redis.scan_match("*").await.unwrap().len(), 2);
+        assert_eq!(redis.del_many(&keys(&["a", "b", "missing"])).await.unwrap(), 2);
+        assert_eq!(redis.connections(), 1);
+        let mut db = redis::Client::open(format!("redis://{}/3", server.addr())).unwrap().get_connection().unwrap();
+        assert_eq!(redis::cmd("DBSIZE").query::<usize>(&mut db).unwrap(), 0);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs redis-server on PATH"]
+    async fn sentinel_follows_the_master_across_failovers() {
+        let master = Server::start("");
+        let replica = Server::start(&format!("replicaof 127.0.0.1 {}", master.port));
+        wait_for("the replica to sync", || {
+            replica.query::<String>(redis::cmd("INFO").arg("replication")).contains("master_link_status:up")
+        });
+        let sentinel = Server::sentinel(&format!(
+            "sentinel monitor mymaster 127.0.0.1 {} 1\nsentinel down-after-milliseconds mymaster 1000",
+            master.port
+        ));
+
+        // The host of REDIS_URI is ignored, its database applies on the master
+        let config = config(RedisMode::Sentinel, vec!["127.0.0.1:1".to_string(), sentinel.addr()], vec![]);
+        let redis = RedisDB::connect("redis://unused:6379/0".to_string(), &config).unwrap();
+        assert!(redis.set("before".to_string(), "1".to_string(), 0).await);
+        assert_eq!(master.query::<Option<String>>(redis::cmd("GET").arg("before")).as_deref(), Some("1"));
+
+        wait_for("the failover", || {
+            let mut failover = redis::cmd("SENTINEL");
+            failover.arg("FAILOVER").arg("mymaster");
+            sentinel.conn().ok().map(|mut conn| failover.query::<String>(&mut conn).is_ok()) == Some(true)
+        });
+        wait_for("the replica to be promoted", || {
+            let role: Vec<redis::Value> = replica.query(&mut redis::cmd("ROLE"));
+            let named: Option<(String, u16)> =
+                sentinel.query(redis::cmd("SENTINEL").arg("get-master-addr-by-name").arg("mymaster"));
+            named.map(|(_, port)| port) == Some(replica.port) && role[0] == redis::Value::Data(b"master".to_vec())
+        });
+
+        // The first write may still reach the old master and come back READONLY
+        let mut written = false;
+        for _ in 0..50 {
+            if redis.set("after".to_string(), "2".to_string(), 0).await {
+                written = true;
+                break;
+            }
+            delay_for(Duration::from_millis(100)).await;
+        }
+        assert!(written);
+        assert_eq!(replica.query::<Option<String>>(redis::cmd("GET").arg("after")).as_deref(), Some("2"));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs redis-server on PATH"]
+    async fn cluster_routes_by_slot_and_follows_redirects() {
+        let nodes: Vec<Server> = (0..3)
+            .map(|_| Server::start("cluster-enabled yes\ncluster-config-file nodes.conf\ncluster-node-timeout 5000"))
+            .collect();
+        let per_node = SLOTS / 3 + 1;
+        let owner = |slot: u16| (slot / per_node) as usize;
+        for (index, node) in nodes.iter().enumerate() {
+            let slots: Vec<u16> = (0..SLOTS).filter(|slot| owner(*slot) == index).collect();
+            node.query::<()>(redis::cmd("CLUSTER").arg("ADDSLOTS").arg(slots));
+        }
+        for node in &nodes[1..] {
+            nodes[0].query::<()>(redis::cmd("CLUSTER").arg("MEET").arg("127.0.0.1").arg(node.port));
+        }
+        wait_for("the cluster to form", || {
+            nodes.iter().all(|node| {
+                let info: String = node.query(redis::cmd("CLUSTER").arg("INFO"));
+                info.contains("cluster_state:ok") && info.contains("cluster_known_nodes:3")
+            })
+        });
+        let id = |index: usize| nodes[index].query::<String>(redis::cmd("CLUSTER").arg("MYID"));
+
+        let config = config(RedisMode::Cluster, vec![], vec![nodes[1].addr()]);
+        let redis = RedisDB::connect(format!("redis://{}", nodes[0].addr()), &config).unwrap();
+        redis.ping(Duration::from_secs(5)).await.unwrap();
+
+        // Keys of every node, read back in order with one MGET per slot
+        let names = keys(&["a", "b", "c", "d", "e", "f", "g", "h", "{a}1", "{a}2"]);
+        for name in &names {
+            assert!(redis.set(name.clone(), format!("value of {}", name), 0).await);
+        }
+        let mut asked = names.clone();
+        asked.insert(3, "missing".to_string());
+        let values = redis.mget(asked.clone()).await.unwrap();
+        assert_eq!(values[3], None);
+        assert_eq!(values[10].as_deref(), Some("value of {a}2"));
+        assert_eq!(redis.connections(), 3);
+        assert_eq!(redis.scan_match("*").await.unwrap().len(), names.len());
+        assert_eq!(redis.del_many(&asked).await.unwrap(), names.len());
+
+        // MOVED: the slot changes owner behind the client's back
+        let slot = key_slot(b"moved");
+        let target = (owner(slot) + 1) % 3;
+        for node in &nodes {
+            node.query::<()>(redis::cmd("CLUSTER").arg("SETSLOT").arg(slot).arg("NODE").arg(id(target)));
+        }
+        assert!(redis.set("moved".to_string(), "1".to_string(), 0).await);
+        assert_eq!(nodes[target].query::<Option<String>>(redis::cmd("GET").arg("moved")).as_deref(), Some("1"));
+
+        // ASK: the slot is being migrated, new keys go to the importing node
+        let slot = key_slot(b"asked");
+        let (source, target) = (owner(slot), (owner(slot) + 1) % 3);
+        nodes[target].query::<()>(redis::cmd("CLUSTER").arg("SETSLOT").arg(slot).arg("IMPORTING").arg(id(source)));
+        nodes[source].query::<()>(redis::cmd("CLUSTER").arg("SETSLOT").arg(slot).arg("MIGRATING").arg(id(target)));
+        assert!(redis.set("asked".to_string(), "2".to_string(), 0).await);
+        assert_eq!(redis.get::<Option<String>>("asked".to_string()).await.unwrap().as_deref(), Some("2"));
+
+        let mut conn = nodes[target].conn().unwrap();
+        redis::cmd("ASKING").query::<()>(&mut conn).unwrap();
+        assert_eq!(redis::cmd("GET").arg("asked").query::<Option<String>>(&mut conn).unwrap().as_deref(), Some("2"));
+    }
+}
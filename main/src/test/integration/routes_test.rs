@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod test {
+    use crate::routes::routes::{RouteInfo, RouteScope};
+    use crate::routes::{init_admin_routes, init_ops_routes, init_routes, ROUTES};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, App};
+
+    fn request(route: &RouteInfo) -> test::TestRequest {
+        let method = Method::from_bytes(route.method.as_bytes()).unwrap();
+        // Any value will do for the path parameters
+        let uri: Vec<&str> = route.path.split('/').map(|part| if part.starts_with('{') { "1" } else { part }).collect();
+
+        test::TestRequest::default().method(method).uri(&uri.join("/"))
+    }
+
+    #[actix_rt::test]
+    async fn every_listed_route_is_registered() {
+        let app = App::new().configure(init_routes).configure(init_ops_routes).configure(init_admin_routes);
+        let mut app = test::init_service(app).await;
+
+        for route in ROUTES {
+            let response = test::call_service(&mut app, request(route).to_request()).await;
+
+            assert_ne!(response.status(), StatusCode::NOT_FOUND, "{} {} is not registered", route.method, route.path);
+        }
+
+        let req = test::TestRequest::get().uri("/not-a-route").to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn each_scope_registers_only_its_routes() {
+        let mut public = test::init_service(App::new().configure(init_routes)).await;
+        let mut ops = test::init_service(App::new().configure(init_ops_routes)).await;
+        let mut admin = test::init_service(App::new().configure(init_admin_routes)).await;
+
+        for route in ROUTES {
+            let apps = [(RouteScope::Public, &mut public), (RouteScope::Ops, &mut ops), (RouteScope::Admin, &mut admin)];
+            for (scope, app) in apps {
+                let response = test::call_service(app, request(route).to_request()).await;
+
+                let registered = response.status() != StatusCode::NOT_FOUND;
+                assert_eq!(registered, route.scope == scope, "{} {} in {}", route.method, route.path, scope.as_str());
+            }
+        }
+    }
+}
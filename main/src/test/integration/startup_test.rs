@@ -0,0 +1,26 @@
+#[cfg(test)]
+mod test {
+    use crate::app::Server;
+    use crate::components::databases::redis_db::RedisDB;
+    use crate::config::{Config, DependencyPolicy, CONFIG};
+    use crate::services::iam_service::IamKeys;
+    use crate::test::fakes::SilentIam;
+    use actix_rt::time::timeout;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn an_iam_that_never_answers_does_not_hang_the_startup() {
+        let mut config: Config = (*CONFIG.get()).clone();
+        config.startup.deadline = 0;
+        config.startup.redis = DependencyPolicy::Degraded;
+        config.startup.iam = DependencyPolicy::Degraded;
+        config.health.timeout = 50;
+        // Nothing listens on port 1
+        let redis = RedisDB::connect("redis://127.0.0.1:1/".to_string(), &config.redis).unwrap();
+        let iam_keys = IamKeys::new(SilentIam);
+
+        let started = timeout(Duration::from_secs(2), Server::wait_for_dependencies(&config, &redis, &iam_keys)).await;
+
+        assert_eq!(started, Ok(Ok(())));
+    }
+}
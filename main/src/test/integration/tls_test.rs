@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::{BigNum, MsbOption};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslConnector, SslFiletype, SslMethod};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509NameBuilder, X509};
+
+    use crate::components::tls::{self, PeerCertificate};
+    use crate::config::TlsConfig;
+
+    struct Pem {
+        cert: PathBuf,
+        key: PathBuf,
+    }
+
+    /// Self-signed when `issuer` is None
+    fn certificate(dir: &Path, name: &str, issuer: Option<(&X509, &PKey<Private>)>) -> (X509, PKey<Private>, Pem) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        match issuer {
+            Some((issuer_cert, _)) => {
+                builder.set_issuer_name(issuer_cert.subject_name()).unwrap();
+                let san = SubjectAlternativeName::new().dns(name).build(&builder.x509v3_context(Some(issuer_cert), None));
+                builder.append_extension(san.unwrap()).unwrap();
+            },
+            None => {
+                builder.set_issuer_name(&subject).unwrap();
+                builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+            },
+        }
+        let signing_key = issuer.map(|(_, key)| key).unwrap_or(&key);
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+
+        let pem = Pem {
+            cert: dir.join(format!("{}.crt", name)),
+            key: dir.join(format!("{}.key", name)),
+        };
+        std::fs::write(&pem.cert, cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&pem.key, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        (cert, key, pem)
+    }
+
+    async fn peer(req: HttpRequest) -> HttpResponse {
+        match req.extensions().get::<PeerCertificate>() {
+            Some(peer) => HttpResponse::Ok().body(peer.subject.clone()),
+            None => HttpResponse::Ok().body("anonymous"),
+        }
+    }
+
+    /// Blocking HTTPS GET, returns the raw response
+    fn get(addr: SocketAddr, ca: &Path, client: Option<&Pem>) -> Result<String, String> {
+        let mut connector = SslConnector::builder(SslMethod::tls()).map_err(|err| err.to_string())?;
+        connector.set_ca_file(ca).map_err(|err| err.to_string())?;
+        if let Some(client) = client {
+            connector.set_certificate_file(&client.cert, SslFiletype::PEM).map_err(|err| err.to_string())?;
+            connector.set_private_key_file(&client.key, SslFiletype::PEM).map_err(|err| err.to_string())?;
+        }
+
+        let stream = TcpStream::connect(addr).map_err(|err| err.to_string())?;
+        let mut stream = connector.build().connect("localhost", stream).map_err(|err| err.to_string())?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .map_err(|err| err.to_string())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|err| err.to_string())?;
+
+        Ok(response)
+    }
+
+    #[actix_rt::test]
+    async fn verifies_optional_client_certificates() {
+        let dir = std::env::temp_dir().join(format!("tls_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (ca_cert, ca_key, ca) = certificate(&dir, "test-ca", None);
+        let (_, _, server) = certificate(&dir, "localhost", Some((&ca_cert, &ca_key)));
+        let (_, _, client) = certificate(&dir, "post-service", Some((&ca_cert, &ca_key)));
+        let (_, _, stranger) = certificate(&dir, "stranger", None);
+
+        let config = TlsConfig {
+            cert_path: Some(server.cert.clone()),
+            key_path: Some(server.key.clone()),
+            client_ca_path: Some(ca.cert.clone()),
+            redirect_bind: None,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let srv = HttpServer::new(|| App::new().route("/", web::get().to(peer)))
+            .workers(1)
+            .disable_signals()
+            .on_connect(tls::on_connect)
+            .listen_openssl(listener, tls::acceptor(&config).unwrap())
+            .unwrap()
+            .run();
+
+        let ca_path = ca.cert.clone();
+        let responses = web::block(move || -> Result<_, ()> {
+            Ok((
+                get(addr, &ca_path, None),
+                get(addr, &ca_path, Some(&client)),
+                get(addr, &ca_path, Some(&stranger)),
+            ))
+        })
+        .await
+        .unwrap();
+        srv.stop(true).await;
+        std::fs::remove_dir_all(dir).ok();
+
+        let (anonymous, verified, untrusted) = responses;
+        assert!(anonymous.unwrap().ends_with("anonymous"));
+        assert!(verified.unwrap().ends_with("CN=post-service"));
+        // Depending on the TLS version the rejection shows up on connect or on read
+        assert!(!untrusted.unwrap_or_default().contains("200 OK"));
+    }
+
+    #[test]
+    fn rejects_a_key_that_does_not_match_the_certificate() {
+        let dir = std::env::temp_dir().join(format!("tls_key_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (_, _, first) = certificate(&dir, "first", None);
+        let (_, _, second) = certificate(&dir, "second", None);
+
+        let config = TlsConfig {
+            cert_path: Some(first.cert),
+            key_path: Some(second.key),
+            ..TlsConfig::default()
+        };
+        let result = tls::acceptor(&config);
+        std::fs::remove_dir_all(dir).ok();
+
+        assert!(result.is_err());
+    }
+}
@@ -1 +1,3 @@
+#[cfg(test)]
+pub mod fakes;
 pub mod integration;
@@ -1,29 +1,285 @@
-use actix_web::HttpRequest;
+//! What of a request is attached to its error report
+//!
+//! Only the headers of ERROR_CAPTURE_HEADERS are copied, credentials among
+//! them redacted. The body is kept up to ERROR_CAPTURE_BODY_LIMIT bytes by the
+//! `Report` middleware; JSON bodies and the query string have the values of
+//! ERROR_CAPTURE_REDACT_FIELDS redacted.
+
 use std::collections::HashMap;
+
+use actix_web::dev::{Extensions, ServiceRequest};
+use actix_web::http::{header, HeaderMap, Method};
+use actix_web::web::Bytes;
+use actix_web::{HttpMessage, HttpRequest};
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+use crate::config::{secret::REDACTED, ErrorCaptureConfig, CONFIG};
 use crate::errors::RequestInfo;
-use actix_web::dev::ServiceRequest;
+use crate::middlewares::localize_middleware::RequestId;
 
-#[allow(unused)]
-pub fn get_request_info(req: &HttpRequest) -> Option<RequestInfo> {
-    let headers: HashMap<String, String> = HashMap::new();
+/// Headers whose values never leave the service, even when allowlisted
+pub const REDACTED_HEADERS: &[&str] = &[
+    "x-gapo-api-key",
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
 
-    // for (key, value) in req.headers().iter() {
-    //     headers.insert(key.to_string(), value.to_str().unwrap_or("").to_string());
-    // }
-    
-    let tags: HashMap<String, String> = HashMap::new();
-    let body: HashMap<String, String> = HashMap::new();
-    let uri: String = req.uri().to_string();
+/// The start of a request body, kept in the request extensions by `Report`
+#[derive(Clone, Debug)]
+pub struct CapturedBody {
+    pub bytes: Bytes,
+    /// False when the body went on past `bytes`
+    pub complete: bool,
+}
 
-    Some(RequestInfo { headers: Some(headers), tags: Some(tags), uri: Some(uri), body: Some(body)})
+pub fn get_request_info(req: &HttpRequest) -> Option<RequestInfo> {
+    request_info(req.method(), req.path(), req.query_string(), req.headers(), &req.extensions())
 }
 
+/// `get_request_info` before the request is handled; a ServiceRequest does not lend its HttpRequest
 pub fn get_request_info_from_service_request(req: &ServiceRequest) -> Option<RequestInfo> {
-    let headers: HashMap<String, String> = HashMap::new();
+    request_info(req.method(), req.path(), req.query_string(), req.headers(), &req.extensions())
+}
+
+fn request_info(
+    method: &Method,
+    path: &str,
+    query: &str,
+    headers: &HeaderMap,
+    extensions: &Extensions,
+) -> Option<RequestInfo> {
+    Some(capture(
+        &CONFIG.get().error_capture,
+        method.as_str(),
+        path,
+        query,
+        headers,
+        extensions.get::<RequestId>(),
+        extensions.get::<CapturedBody>(),
+    ))
+}
+
+/**
+ * The request context of an error report, redacted per `config`
+ **/
+pub fn capture(
+    config: &ErrorCaptureConfig,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &HeaderMap,
+    request_id: Option<&RequestId>,
+    body: Option<&CapturedBody>,
+) -> RequestInfo {
+    let mut captured_headers = HashMap::new();
+    for (name, value) in headers.iter() {
+        let name = name.as_str();
+        if !config.headers.iter().any(|allowed| allowed == name) {
+            continue;
+        }
+
+        let value = if REDACTED_HEADERS.contains(&name) {
+            REDACTED.to_string()
+        } else {
+            value.to_str().unwrap_or("<binary>").to_string()
+        };
+        captured_headers.insert(name.to_string(), value);
+    }
+
+    let mut tags = HashMap::new();
+    tags.insert("method".to_string(), method.to_string());
+    if let Some(request_id) = request_id {
+        tags.insert("request_id".to_string(), request_id.0.clone());
+    }
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    RequestInfo {
+        headers: Some(captured_headers),
+        tags: Some(tags),
+        body: body.and_then(|body| redact_body(config, content_type, body)),
+        uri: Some(path.to_string()),
+        query: Some(redact_query(config, query)).filter(|query| !query.is_empty()),
+    }
+}
+
+fn is_redacted(config: &ErrorCaptureConfig, field: &str) -> bool {
+    config
+        .redact_fields
+        .iter()
+        .any(|redacted| redacted.eq_ignore_ascii_case(field))
+}
 
-    let tags: HashMap<String, String> = HashMap::new();
-    let body: HashMap<String, String> = HashMap::new();
-    let uri: String = req.uri().to_string();
+/**
+ * The query string with the values of redacted fields replaced; names are
+ * compared decoded, so `pass%77ord` is redacted as `password`
+ **/
+pub fn redact_query(config: &ErrorCaptureConfig, query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_redacted(config, &percent_decode_str(name).decode_utf8_lossy()) => {
+                format!("{}={}", name, REDACTED)
+            },
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/**
+ * JSON with the values of redacted fields replaced, at any depth
+ **/
+pub fn redact_json(config: &ErrorCaptureConfig, value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = if is_redacted(config, &key) {
+                        Value::from(REDACTED)
+                    } else {
+                        redact_json(config, value)
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| redact_json(config, item)).collect()),
+        other => other,
+    }
+}
+
+/**
+ * The captured body as JSON when it parses, as text otherwise. A cut JSON
+ * body cannot be redacted, so only its size is reported; other binary or
+ * form bodies are left out the same way.
+ **/
+fn redact_body(config: &ErrorCaptureConfig, content_type: &str, body: &CapturedBody) -> Option<Value> {
+    if body.bytes.is_empty() {
+        return None;
+    }
+    let left_out = || {
+        let more = if body.complete { "" } else { "+" };
+        Some(Value::from(format!(
+            "<{}{} bytes of {}>",
+            body.bytes.len(),
+            more,
+            content_type
+        )))
+    };
+
+    if content_type.starts_with("application/json") || content_type.ends_with("+json") {
+        if !body.complete {
+            return left_out();
+        }
+        return match serde_json::from_slice(&body.bytes) {
+            Ok(json) => Some(redact_json(config, json)),
+            Err(_) => left_out(),
+        };
+    }
+
+    match std::str::from_utf8(&body.bytes) {
+        Ok(text) if content_type.starts_with("text/") => {
+            let more = if body.complete { "" } else { "…" };
+            Some(Value::from(format!("{}{}", text, more)))
+        },
+        _ => left_out(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderValue;
+    use serde_json::json;
+
+    fn config() -> ErrorCaptureConfig {
+        ErrorCaptureConfig {
+            headers: vec![
+                "content-type".to_string(),
+                "x-gapo-api-key".to_string(),
+                "x-gapo-role".to_string(),
+            ],
+            body_limit: 64,
+            redact_fields: vec!["password".to_string(), "api_key".to_string()],
+        }
+    }
 
-    Some(RequestInfo { headers: Some(headers), tags: Some(tags), uri: Some(uri), body: Some(body)})
-}
\ No newline at end of file
+    fn body(bytes: &'static [u8], complete: bool) -> CapturedBody {
+        CapturedBody {
+            bytes: Bytes::from_static(bytes),
+            complete,
+        }
+    }
+
+    #[test]
+    fn copies_allowlisted_headers_and_redacts_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer abc"));
+        headers.insert(
+            header::HeaderName::from_static("x-gapo-api-key"),
+            HeaderValue::from_static("key"),
+        );
+        headers.insert(
+            header::HeaderName::from_static("x-gapo-role"),
+            HeaderValue::from_static("service"),
+        );
+        let request_id = RequestId("req-1".to_string());
+        let body = body(br#"{"user": {"name": "a", "Password": "b"}, "ids": [1]}"#, true);
+
+        let info = capture(
+            &config(),
+            "POST",
+            "/users",
+            "api_key=k&page=2",
+            &headers,
+            Some(&request_id),
+            Some(&body),
+        );
+        let headers = info.headers.unwrap();
+
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers["x-gapo-api-key"], REDACTED);
+        assert_eq!(headers["x-gapo-role"], "service");
+        assert_eq!(info.tags.unwrap()["request_id"], "req-1");
+        assert_eq!(info.query.as_deref(), Some("api_key=***&page=2"));
+        assert_eq!(
+            info.body,
+            Some(json!({"user": {"name": "a", "Password": "***"}, "ids": [1]}))
+        );
+    }
+
+    #[test]
+    fn leaves_out_bodies_it_cannot_redact() {
+        let config = config();
+
+        assert_eq!(
+            redact_body(&config, "application/json", &body(br#"{"password": "#, false)),
+            Some(json!("<13+ bytes of application/json>"))
+        );
+        assert_eq!(
+            redact_body(&config, "text/plain", &body(b"hello", false)),
+            Some(json!("hello…"))
+        );
+        assert_eq!(
+            redact_body(&config, "application/x-www-form-urlencoded", &body(b"password=a", true)),
+            Some(json!("<10 bytes of application/x-www-form-urlencoded>"))
+        );
+        assert_eq!(redact_body(&config, "text/plain", &body(b"", true)), None);
+    }
+
+    #[test]
+    fn redacts_percent_encoded_query_names() {
+        let config = config();
+
+        assert_eq!(redact_query(&config, "pass%77ord=a&API%5Fkey=b&page=2"), "pass%77ord=***&API%5Fkey=***&page=2");
+    }
+}